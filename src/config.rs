@@ -1,6 +1,10 @@
+use axum::http::HeaderName;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,12 +13,189 @@ pub struct ConfigError(pub String);
 
 pub type ConfigResult<T> = Result<T, ConfigError>;
 
+/// 旧版 `auth` 令牌对应的客户端名称
+pub const DEFAULT_CLIENT: &str = "default";
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    /// 旧版的单一客户端令牌，等价于名为 `default` 且不受限制的客户端，可以与 `clients` 同时使用
+    #[serde(default)]
     pub auth: String,
+    /// 客户端密钥列表
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+    /// 管理接口令牌，用于查询和调整配额，未配置时这些接口拒绝所有请求
+    pub admin_key: Option<String>,
     pub port: u16,
     pub providers: Vec<Provider>,
     pub log: Option<LogConfig>,
+    pub load_balancing: Option<LoadBalancingConfig>,
+    pub response_headers: Option<ResponseHeadersConfig>,
+    pub budget: Option<BudgetConfig>,
+    /// 全局限流，所有客户端共享
+    pub rate_limit: Option<RateLimitConfig>,
+    /// 按模型别名限流，所有客户端共享
+    #[serde(default)]
+    pub model_rate_limits: HashMap<String, RateLimitConfig>,
+    /// 统计数据持久化，未配置时统计数据只保存在内存中，重启后丢失
+    pub persistence: Option<PersistenceConfig>,
+    /// 请求审计日志，未配置时不记录
+    pub audit_log: Option<AuditLogConfig>,
+    /// 请求和响应内容捕获，用于调试提示词，未配置时不捕获
+    pub capture: Option<CaptureConfig>,
+}
+
+/// 客户端密钥，每个团队或服务使用独立的密钥，可以单独吊销并分别统计用量
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClientConfig {
+    pub name: String,
+    pub key: String,
+    /// 是否启用，默认为 true
+    pub enabled: Option<bool>,
+    /// 过期时间（RFC 3339），过期后认证失败
+    pub expires_at: Option<DateTime<Utc>>,
+    /// 允许访问的模型，为空时允许全部，支持模型别名、`provider:model` 和 `*`/`?` 通配符
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// 禁止访问的模型，优先于 `models`，格式与 `models` 相同，同时匹配别名对应的上游模型
+    #[serde(default)]
+    pub deny_models: Vec<String>,
+    /// 请求频率限制
+    pub rate_limit: Option<RateLimitConfig>,
+    /// 用量配额，用尽后拒绝请求直到周期结束
+    pub quota: Option<QuotaConfig>,
+}
+
+impl ClientConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+/// 限流设置，未配置的项不限制
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct RateLimitConfig {
+    /// 每分钟最多请求数
+    pub requests_per_minute: Option<u32>,
+    /// 每分钟最多 token 数，按响应中的实际用量扣除
+    pub tokens_per_minute: Option<u64>,
+    /// 最多同时处理的请求数
+    pub max_concurrent: Option<u32>,
+}
+
+impl RateLimitConfig {
+    fn validate(&self, owner: &str) -> ConfigResult<()> {
+        if self.requests_per_minute == Some(0)
+            || self.tokens_per_minute == Some(0)
+            || self.max_concurrent == Some(0)
+        {
+            return Err(ConfigError(format!(
+                "Rate limits of {} must be greater than 0",
+                owner
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 客户端用量配额，按 UTC 自然日和自然月计算，未配置的项不限制
+///
+/// 用量来自上游响应中的 `usage`，费用只计入配置了价格的模型
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct QuotaConfig {
+    pub daily_tokens: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+    /// 每天最多费用，单位为美元
+    pub daily_cost: Option<f64>,
+    /// 每月最多费用，单位为美元
+    pub monthly_cost: Option<f64>,
+}
+
+impl QuotaConfig {
+    fn validate(&self, owner: &str) -> ConfigResult<()> {
+        let invalid_tokens = [self.daily_tokens, self.monthly_tokens].contains(&Some(0));
+        let invalid_cost = [self.daily_cost, self.monthly_cost]
+            .iter()
+            .flatten()
+            .any(|cost| !cost.is_finite() || *cost <= 0.0);
+        if invalid_tokens || invalid_cost {
+            return Err(ConfigError(format!(
+                "Quota of {} must be greater than 0",
+                owner
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 统计数据持久化设置，只在启动时读取
+#[derive(Debug, Deserialize, Clone)]
+pub struct PersistenceConfig {
+    /// 存储后端
+    #[serde(default)]
+    pub backend: PersistenceBackend,
+    /// 数据文件路径
+    pub path: String,
+    /// 写入间隔（秒），默认为 30
+    pub flush_interval_secs: Option<u64>,
+}
+
+impl PersistenceConfig {
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval_secs.unwrap_or(30))
+    }
+}
+
+/// 统计数据存储后端
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceBackend {
+    /// 追加写入的 JSON Lines 文件，每行是一份完整快照，启动时读取最后一行
+    #[default]
+    #[serde(alias = "jsonl")]
+    JsonLines,
+}
+
+/// 返回给客户端的响应头策略，逐跳请求头和 `set-cookie` 始终会被移除
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ResponseHeadersConfig {
+    /// 隐藏能识别上游账号的厂商响应头，如 `openai-organization`、`x-ratelimit-*`
+    #[serde(default)]
+    pub hide_vendor_headers: bool,
+    /// 额外隐藏的响应头，名称不区分大小写
+    #[serde(default)]
+    pub hide: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LoadBalancingConfig {
+    /// 默认负载均衡策略
+    #[serde(default)]
+    pub strategy: BalanceStrategy,
+    /// 按模型别名覆盖的策略
+    #[serde(default)]
+    pub aliases: HashMap<String, BalanceStrategy>,
+}
+
+/// 同一别名由多个提供者提供时的负载均衡策略
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    /// 按配置顺序，只在失败时回退
+    #[default]
+    Priority,
+    /// 按权重随机
+    WeightedRandom,
+    /// 轮询
+    RoundRobin,
+    /// 在途请求最少优先
+    LeastInFlight,
+    /// 延迟 EWMA 最低优先，失败的请求按惩罚延迟计入
+    EwmaLatency,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -25,13 +206,167 @@ pub struct LogConfig {
     pub max_file_size: Option<u64>,
 }
 
+/// 请求审计日志设置，只在启动时读取
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuditLogConfig {
+    /// 日志文件路径，每行一条 JSON 记录
+    pub file: String,
+    /// 保留的轮转文件数量，默认为 10
+    pub max_files: Option<usize>,
+    /// 单个文件的最大字节数，默认为 50MB
+    pub max_file_size: Option<u64>,
+}
+
+/// 请求和响应内容捕获设置，文件设置只在启动时读取，其余设置随配置重新加载
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaptureConfig {
+    /// 捕获文件路径，每行一条 JSON 记录
+    pub file: String,
+    /// 保留的轮转文件数量，默认为 10
+    pub max_files: Option<usize>,
+    /// 单个文件的最大字节数，默认为 50MB
+    pub max_file_size: Option<u64>,
+    /// 开启捕获的客户端名称
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// 开启捕获的模型别名，支持 `*`/`?` 通配符
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// 需要脱敏的 JSON 路径，以 `.` 分隔，`*` 匹配任意字段或数组元素，如 `messages.*.content`
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// 单个响应体最多保存的字节数，默认为 1MB
+    pub max_body_bytes: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Provider {
     pub name: String,
+    /// 上游协议类型，默认为 OpenAI 兼容
+    #[serde(rename = "type", default)]
+    pub kind: ProviderKind,
     #[serde(default)]
     pub models: Vec<Model>,
     pub endpoints: Endpoints,
     pub keys: Vec<String>,
+    /// 单次请求最多尝试的次数（包含首次请求），默认等于密钥数量
+    pub max_attempts: Option<usize>,
+    /// 密钥选择策略
+    #[serde(default)]
+    pub key_selection: KeySelectionStrategy,
+    /// 加权策略下每个密钥的权重，与 `keys` 一一对应
+    pub key_weights: Option<Vec<u32>>,
+    /// Anthropic 提供者使用的 `anthropic-version` 请求头
+    pub anthropic_version: Option<String>,
+    /// Azure 提供者使用的 `api-version` 查询参数
+    pub api_version: Option<String>,
+    /// 认证方式，未配置时使用提供者类型的默认方式
+    pub auth: Option<AuthScheme>,
+    /// 附加到上游请求的请求头，值支持 `{key}`、`{model}`、`{provider}` 占位符
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// 附加到上游请求的查询参数，值支持与 `headers` 相同的占位符
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+    /// 透传给上游的客户端请求头，未配置时不透传
+    pub forward_headers: Option<HeaderForwardConfig>,
+    /// 请求超时设置，未配置的项不限制
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    /// 流式请求是否注入 `stream_options.include_usage` 以统计 token 用量，默认为 true
+    ///
+    /// 只对 OpenAI 兼容的提供者生效，上游不接受该字段时设为 false，此时流式请求不统计用量
+    pub stream_usage: Option<bool>,
+}
+
+/// 上游请求超时设置，单位为秒
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct TimeoutConfig {
+    /// 整个请求的总超时，包括读取响应体，流式请求需要留足生成时间
+    pub total_secs: Option<u64>,
+    /// 等待上游返回响应头的超时，超时后换用其他密钥或提供者重试
+    pub first_byte_secs: Option<u64>,
+    /// 流式响应两个数据块之间的最长间隔，超时后中断响应
+    pub idle_secs: Option<u64>,
+}
+
+impl TimeoutConfig {
+    pub fn total(&self) -> Option<Duration> {
+        self.total_secs.map(Duration::from_secs)
+    }
+
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.first_byte_secs.map(Duration::from_secs)
+    }
+
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle_secs.map(Duration::from_secs)
+    }
+}
+
+/// 客户端请求头透传规则，名称不区分大小写
+///
+/// 客户端的认证请求头和逐跳请求头始终不会透传
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HeaderForwardConfig {
+    /// 允许透传的请求头，`*` 表示全部
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// 禁止透传的请求头，优先于 `allow`
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// 上游认证方式
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer {key}`
+    Bearer,
+    /// 自定义请求头，值为可选前缀加密钥
+    Header {
+        name: String,
+        prefix: Option<String>,
+    },
+    /// 通过查询参数传递密钥
+    Query { name: String },
+    /// 不发送密钥
+    None,
+}
+
+/// 上游协议类型
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI 兼容接口，请求和响应原样转发
+    #[default]
+    OpenAI,
+    /// Anthropic Messages API
+    Anthropic,
+    /// Google Gemini generateContent API，`completions` 端点填写到 `/models` 为止的地址
+    Gemini,
+    /// Azure OpenAI，模型映射中的 `model` 填写部署名称
+    Azure,
+}
+
+/// 同一提供者多个密钥之间的选择策略
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySelectionStrategy {
+    /// 轮询
+    RoundRobin,
+    /// 累计使用次数最少优先
+    #[default]
+    LeastUsed,
+    /// 随机
+    Random,
+    /// 按 `key_weights` 加权随机
+    Weighted,
+    /// 按客户端令牌固定密钥
+    Sticky,
+    /// 最久没有被限流的密钥优先
+    #[serde(rename = "least_recent_429")]
+    LeastRecentRateLimited,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -44,9 +379,96 @@ pub struct Endpoints {
 pub struct Model {
     pub alias: String,
     pub model: String,
+    /// 同一别名由多个提供者提供时的优先级，数值越小越先尝试，默认为 0
+    pub priority: Option<u32>,
+    /// 加权随机策略下的权重，默认为 1
+    pub weight: Option<u32>,
+    /// 模型价格，用于估算费用
+    pub pricing: Option<ModelPricing>,
+}
+
+/// 模型价格，单位为每百万 token 的美元价格
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    /// 命中提示缓存的输入价格，未配置时按 `input` 计算
+    pub cached_input: Option<f64>,
+}
+
+/// 费用预算设置
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct BudgetConfig {
+    /// 当天（UTC）累计费用超过这些金额时记录告警日志，单位为美元
+    #[serde(default)]
+    pub daily_alerts: Vec<f64>,
 }
 
 impl Config {
+    /// 获取模型别名使用的负载均衡策略
+    pub fn balance_strategy(&self, alias: &str) -> BalanceStrategy {
+        self.load_balancing
+            .as_ref()
+            .map(|lb| lb.aliases.get(alias).copied().unwrap_or(lb.strategy))
+            .unwrap_or_default()
+    }
+
+    /// 模型名称是否为配置中的别名，或者提供者下已配置的 `provider:model`
+    pub fn is_configured_model(&self, model: &str) -> bool {
+        match model.split_once(':') {
+            Some((provider_name, model_name)) => self.providers.iter().any(|provider| {
+                provider.name == provider_name
+                    && provider.models.iter().any(|m| m.model == model_name)
+            }),
+            None => self
+                .providers
+                .iter()
+                .any(|provider| provider.models.iter().any(|m| m.alias == model)),
+        }
+    }
+
+    /// 查找模型的限流设置，返回设置所属的别名
+    ///
+    /// `provider:model` 格式按该提供者下对应上游模型的别名查找，其次把模型名称当作别名，
+    /// 与直接使用别名的请求共享同一个限流
+    pub fn model_rate_limit<'a>(&'a self, model: &'a str) -> Option<(&'a str, RateLimitConfig)> {
+        let aliases: Vec<&str> = match model.split_once(':') {
+            Some((provider_name, model_name)) => self
+                .providers
+                .iter()
+                .filter(|provider| provider.name == provider_name)
+                .flat_map(|provider| provider.models.iter())
+                .filter(|m| m.model == model_name)
+                .map(|m| m.alias.as_str())
+                .chain(std::iter::once(model_name))
+                .collect(),
+            None => vec![model],
+        };
+        aliases
+            .into_iter()
+            .find_map(|alias| Some((alias, *self.model_rate_limits.get(alias)?)))
+    }
+
+    /// 按令牌查找客户端，旧版 `auth` 令牌对应名为 `default` 的客户端
+    pub fn find_client(&self, token: &str) -> Option<ClientConfig> {
+        if !self.auth.is_empty() && token == self.auth {
+            return Some(ClientConfig {
+                name: DEFAULT_CLIENT.to_string(),
+                key: self.auth.clone(),
+                enabled: None,
+                expires_at: None,
+                models: Vec::new(),
+                deny_models: Vec::new(),
+                rate_limit: None,
+                quota: None,
+            });
+        }
+        self.clients
+            .iter()
+            .find(|client| client.key == token)
+            .cloned()
+    }
+
     pub fn new() -> ConfigResult<Self> {
         let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "./config.json".to_string());
 
@@ -65,8 +487,90 @@ impl Config {
     }
 
     fn validate(&self) -> ConfigResult<()> {
-        if self.auth.is_empty() {
-            return Err(ConfigError("Auth token cannot be empty".to_string()));
+        if self.auth.is_empty() && self.clients.is_empty() {
+            return Err(ConfigError(
+                "Either auth token or at least one client must be configured".to_string(),
+            ));
+        }
+
+        let mut client_names = HashSet::new();
+        let mut client_keys = HashSet::new();
+        for client in &self.clients {
+            if client.key.is_empty() {
+                return Err(ConfigError(format!(
+                    "Client '{}' key cannot be empty",
+                    client.name
+                )));
+            }
+            if client.name == DEFAULT_CLIENT && !self.auth.is_empty() {
+                return Err(ConfigError(format!(
+                    "Client name '{}' is reserved for the auth token",
+                    DEFAULT_CLIENT
+                )));
+            }
+            if !client_names.insert(client.name.as_str()) {
+                return Err(ConfigError(format!(
+                    "Duplicate client name '{}'",
+                    client.name
+                )));
+            }
+            if !client_keys.insert(client.key.as_str()) || client.key == self.auth {
+                return Err(ConfigError(format!(
+                    "Client '{}' key is already used by another client",
+                    client.name
+                )));
+            }
+            if let Some(rate_limit) = &client.rate_limit {
+                rate_limit.validate(&format!("client '{}'", client.name))?;
+            }
+            if let Some(quota) = &client.quota {
+                quota.validate(&format!("client '{}'", client.name))?;
+            }
+        }
+
+        if let Some(admin_key) = &self.admin_key {
+            if admin_key.is_empty() {
+                return Err(ConfigError("Admin key cannot be empty".to_string()));
+            }
+            if *admin_key == self.auth || client_keys.contains(admin_key.as_str()) {
+                return Err(ConfigError(
+                    "Admin key must differ from the auth token and client keys".to_string(),
+                ));
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate("global rate_limit")?;
+        }
+        for (alias, rate_limit) in &self.model_rate_limits {
+            rate_limit.validate(&format!("model '{}'", alias))?;
+        }
+
+        if let Some(persistence) = &self.persistence {
+            if persistence.path.is_empty() {
+                return Err(ConfigError("Persistence path cannot be empty".to_string()));
+            }
+            if persistence.flush_interval_secs == Some(0) {
+                return Err(ConfigError(
+                    "Persistence flush_interval_secs must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        if self
+            .audit_log
+            .as_ref()
+            .is_some_and(|audit| audit.file.is_empty())
+        {
+            return Err(ConfigError("Audit log file cannot be empty".to_string()));
+        }
+
+        if self
+            .capture
+            .as_ref()
+            .is_some_and(|capture| capture.file.is_empty())
+        {
+            return Err(ConfigError("Capture file cannot be empty".to_string()));
         }
 
         if self.providers.is_empty() {
@@ -83,6 +587,73 @@ impl Config {
                 )));
             }
 
+            if let Some(weights) = &provider.key_weights {
+                if weights.len() != provider.keys.len() {
+                    return Err(ConfigError(format!(
+                        "Provider '{}' key_weights must have the same length as keys",
+                        provider.name
+                    )));
+                }
+            }
+
+            if let Some(AuthScheme::Header { name, .. }) = &provider.auth {
+                if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(ConfigError(format!(
+                        "Provider '{}' has invalid auth header name '{}'",
+                        provider.name, name
+                    )));
+                }
+            }
+
+            for name in provider.headers.keys() {
+                if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(ConfigError(format!(
+                        "Provider '{}' has invalid header name '{}'",
+                        provider.name, name
+                    )));
+                }
+            }
+
+            let timeouts = provider.timeouts;
+            if [
+                timeouts.total_secs,
+                timeouts.first_byte_secs,
+                timeouts.idle_secs,
+            ]
+            .contains(&Some(0))
+            {
+                return Err(ConfigError(format!(
+                    "Provider '{}' timeouts must be greater than 0",
+                    provider.name
+                )));
+            }
+
+            for model in &provider.models {
+                if let Some(pricing) = model.pricing {
+                    let prices = [
+                        pricing.input,
+                        pricing.output,
+                        pricing.cached_input.unwrap_or(0.0),
+                    ];
+                    if prices
+                        .iter()
+                        .any(|price| !price.is_finite() || *price < 0.0)
+                    {
+                        return Err(ConfigError(format!(
+                            "Provider '{}' model '{}' has invalid pricing",
+                            provider.name, model.alias
+                        )));
+                    }
+                }
+            }
+
+            if provider.max_attempts == Some(0) {
+                return Err(ConfigError(format!(
+                    "Provider '{}' max_attempts must be greater than 0",
+                    provider.name
+                )));
+            }
+
             // 验证至少有一个端点被配置
             if provider.endpoints.completions.is_none() && provider.endpoints.embeddings.is_none() {
                 return Err(ConfigError(format!(
@@ -95,3 +666,55 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> Config {
+        serde_json::from_value(json!({
+            "auth": "tok",
+            "port": 8080,
+            "providers": [{
+                "name": "openai",
+                "models": [
+                    {"alias": "gpt-4", "model": "gpt-4-0613"},
+                    {"alias": "mini", "model": "gpt-4o-mini"}
+                ],
+                "endpoints": {"completions": "https://api.openai.com/v1/chat/completions"},
+                "keys": ["sk-1"]
+            }],
+            "model_rate_limits": {
+                "gpt-4": {"requests_per_minute": 10},
+                "gpt-4o": {"requests_per_minute": 5}
+            }
+        }))
+        .unwrap()
+    }
+
+    fn limit_alias<'a>(config: &'a Config, model: &'a str) -> Option<&'a str> {
+        config.model_rate_limit(model).map(|(alias, _)| alias)
+    }
+
+    #[test]
+    fn model_rate_limit_by_alias() {
+        let config = config();
+        let (alias, limit) = config.model_rate_limit("gpt-4").unwrap();
+        assert_eq!(alias, "gpt-4");
+        assert_eq!(limit.requests_per_minute, Some(10));
+        assert_eq!(limit_alias(&config, "mini"), None);
+    }
+
+    #[test]
+    fn model_rate_limit_for_provider_model_uses_alias() {
+        let config = config();
+        // 上游模型名称对应到配置的别名
+        assert_eq!(limit_alias(&config, "openai:gpt-4-0613"), Some("gpt-4"));
+        // 模型名称本身就是别名
+        assert_eq!(limit_alias(&config, "openai:gpt-4"), Some("gpt-4"));
+        assert_eq!(limit_alias(&config, "openai:gpt-4o"), Some("gpt-4o"));
+        assert_eq!(limit_alias(&config, "openai:gpt-4o-mini"), None);
+        assert_eq!(limit_alias(&config, "other:gpt-4-0613"), None);
+    }
+}
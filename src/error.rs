@@ -1,15 +1,16 @@
 use crate::config::ConfigError;
 use axum::{
-    http::StatusCode,
+    extract::rejection::JsonRejection,
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Json, Response},
 };
-use serde_json::json;
+use serde_json::{json, Value};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("HTTP error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(reqwest::Error),
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
@@ -22,26 +23,288 @@ pub enum AppError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+
+    /// 请求的模型别名不存在
+    #[error("Model not found: {0}")]
+    ModelNotFound(String),
+
+    /// 提供者不支持请求的端点
+    #[error("Endpoint not supported: {0}")]
+    EndpointNotSupported(String),
+
+    /// 没有可用的上游，如所有密钥都处于冷却或禁用状态
+    #[error("Upstream unavailable: {0}")]
+    UpstreamUnavailable(String),
+
+    /// 上游在配置的超时时间内没有响应
+    #[error("Upstream timeout: {0}")]
+    UpstreamTimeout(String),
+
+    /// 客户端令牌无效
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// 客户端无权访问请求的模型
+    #[error("Model not allowed: {0}")]
+    ModelNotAllowed(String),
+
+    /// 客户端请求过于频繁，`retry_after` 为建议的重试等待秒数，`headers` 为附加的限流状态响应头
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<u64>,
+        headers: Box<HeaderMap>,
+    },
+
+    /// 客户端的用量配额已用尽
+    #[error("Insufficient quota: {0}")]
+    InsufficientQuota(String),
+
+    /// 客户端 IP 已被封禁
+    #[error("IP banned: {0}")]
+    IpBanned(String),
+
+    /// 上游返回的错误，保留上游状态码并按 OpenAI 错误格式返回，
+    /// `retry_after` 为上游限流时通过 `Retry-After` 给出的等待秒数
+    #[error("Upstream error ({status}): {message}")]
+    Upstream {
+        status: StatusCode,
+        message: String,
+        error_type: Option<String>,
+        code: Option<Value>,
+        param: Option<Value>,
+        retry_after: Option<u32>,
+    },
+}
+
+/// 返回给客户端的稳定错误码，客户端可以据此区分错误原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidRequest,
+    ModelNotFound,
+    EndpointNotSupported,
+    InvalidAuth,
+    ModelNotAllowed,
+    IpBanned,
+    RateLimited,
+    InsufficientQuota,
+    UpstreamError,
+    UpstreamTimeout,
+    UpstreamUnavailable,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::ModelNotFound => "model_not_found",
+            ErrorCode::EndpointNotSupported => "endpoint_not_supported",
+            ErrorCode::InvalidAuth => "invalid_auth",
+            ErrorCode::ModelNotAllowed => "model_not_allowed",
+            ErrorCode::IpBanned => "ip_banned",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InsufficientQuota => "insufficient_quota",
+            ErrorCode::UpstreamError => "upstream_error",
+            ErrorCode::UpstreamTimeout => "upstream_timeout",
+            ErrorCode::UpstreamUnavailable => "upstream_unavailable",
+            ErrorCode::InternalError => "internal_error",
+        }
+    }
+
+    /// 错误码所属的 OpenAI 错误类型，认证失败和 IP 封禁沿用早期版本的 `auth_error` 和 `ip_banned`
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::ModelNotFound
+            | ErrorCode::EndpointNotSupported => "invalid_request_error",
+            ErrorCode::InvalidAuth => "auth_error",
+            ErrorCode::IpBanned => "ip_banned",
+            ErrorCode::ModelNotAllowed => "permission_error",
+            ErrorCode::RateLimited => "rate_limit_error",
+            ErrorCode::InsufficientQuota => "insufficient_quota",
+            ErrorCode::UpstreamError
+            | ErrorCode::UpstreamTimeout
+            | ErrorCode::UpstreamUnavailable => "upstream_error",
+            ErrorCode::InternalError => "server_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::EndpointNotSupported => StatusCode::BAD_REQUEST,
+            ErrorCode::ModelNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::InvalidAuth => StatusCode::UNAUTHORIZED,
+            ErrorCode::ModelNotAllowed | ErrorCode::IpBanned => StatusCode::FORBIDDEN,
+            ErrorCode::RateLimited | ErrorCode::InsufficientQuota => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::UpstreamError | ErrorCode::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+            ErrorCode::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 上游错误没有错误码时，按状态码推断
+    fn from_upstream_status(status: StatusCode) -> Self {
+        match status.as_u16() {
+            401 | 403 => ErrorCode::UpstreamError,
+            429 => ErrorCode::RateLimited,
+            504 => ErrorCode::UpstreamTimeout,
+            502 | 503 => ErrorCode::UpstreamUnavailable,
+            400..=499 => ErrorCode::InvalidRequest,
+            _ => ErrorCode::UpstreamError,
+        }
+    }
+}
+
+impl AppError {
+    /// 错误对应的错误码
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Http(e) if e.is_timeout() => ErrorCode::UpstreamTimeout,
+            AppError::Http(_) => ErrorCode::UpstreamUnavailable,
+            AppError::Json(_) | AppError::Validation(_) => ErrorCode::InvalidRequest,
+            AppError::Config(_) | AppError::Internal(_) => ErrorCode::InternalError,
+            AppError::ModelNotFound(_) => ErrorCode::ModelNotFound,
+            AppError::EndpointNotSupported(_) => ErrorCode::EndpointNotSupported,
+            AppError::UpstreamUnavailable(_) => ErrorCode::UpstreamUnavailable,
+            AppError::UpstreamTimeout(_) => ErrorCode::UpstreamTimeout,
+            AppError::Unauthorized(_) => ErrorCode::InvalidAuth,
+            AppError::ModelNotAllowed(_) => ErrorCode::ModelNotAllowed,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
+            AppError::InsufficientQuota(_) => ErrorCode::InsufficientQuota,
+            AppError::IpBanned(_) => ErrorCode::IpBanned,
+            AppError::Upstream { status, .. } => ErrorCode::from_upstream_status(*status),
+        }
+    }
+
+    /// 返回给客户端的状态码，上游错误保留上游的状态码，上游认证失败返回 502
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Upstream { .. } if self.is_upstream_auth_failure() => StatusCode::BAD_GATEWAY,
+            AppError::Upstream { status, .. } => *status,
+            other => other.code().status(),
+        }
+    }
+
+    /// 上游拒绝了代理使用的密钥，属于代理的配置问题，与客户端的凭证无关
+    fn is_upstream_auth_failure(&self) -> bool {
+        matches!(self, AppError::Upstream { status, .. } if matches!(status.as_u16(), 401 | 403))
+    }
+
+    /// 返回给客户端的错误信息，不带错误类别前缀
+    fn message(&self) -> String {
+        match self {
+            AppError::Http(e) => e.to_string(),
+            AppError::Json(e) => e.to_string(),
+            AppError::Config(e) => e.to_string(),
+            AppError::Validation(e)
+            | AppError::Internal(e)
+            | AppError::ModelNotFound(e)
+            | AppError::EndpointNotSupported(e)
+            | AppError::UpstreamUnavailable(e)
+            | AppError::UpstreamTimeout(e)
+            | AppError::Unauthorized(e)
+            | AppError::ModelNotAllowed(e)
+            | AppError::InsufficientQuota(e)
+            | AppError::IpBanned(e) => e.clone(),
+            AppError::RateLimited { message, .. } => message.clone(),
+            AppError::Upstream { message, .. } => message.clone(),
+        }
+    }
+}
+
+/// 去掉错误中的请求地址，Gemini 等提供者的密钥放在查询参数中，不能出现在日志和响应里
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::Http(e.without_url())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::Validation(rejection.body_text())
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_msg) = match &self {
-            AppError::Http(e) => (StatusCode::BAD_GATEWAY, e.to_string()),
-            AppError::Json(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            AppError::Config(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
-            AppError::Validation(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            AppError::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        let code = self.code();
+        let (retry_after, extra_headers) = match &self {
+            AppError::RateLimited {
+                retry_after,
+                headers,
+                ..
+            } => (*retry_after, *headers.clone()),
+            AppError::Upstream { retry_after, .. } if !self.is_upstream_auth_failure() => {
+                (retry_after.map(u64::from), HeaderMap::new())
+            }
+            _ => (None, HeaderMap::new()),
+        };
+        let (status, message, error_type, error_code, param) = match self {
+            // 上游的错误信息可能包含密钥片段，只返回通用信息
+            AppError::Upstream { status, .. } if self.is_upstream_auth_failure() => (
+                StatusCode::BAD_GATEWAY,
+                format!(
+                    "Upstream provider rejected the request with status {}",
+                    status.as_u16()
+                ),
+                code.error_type().to_string(),
+                json!(code.as_str()),
+                None,
+            ),
+            // 上游错误保留上游的状态码、类型和错误码
+            AppError::Upstream {
+                status,
+                message,
+                error_type,
+                code: upstream_code,
+                param,
+                ..
+            } => (
+                status,
+                message,
+                error_type.unwrap_or_else(|| code.error_type().to_string()),
+                upstream_code.unwrap_or_else(|| json!(code.as_str())),
+                param,
+            ),
+            other => (
+                code.status(),
+                other.message(),
+                code.error_type().to_string(),
+                json!(code.as_str()),
+                None,
+            ),
         };
 
         let error_response = json!({
             "error": {
-                "message": error_msg,
+                "message": message,
+                "type": error_type,
+                "code": error_code,
+                "param": param,
             }
         });
 
-        (status, Json(error_response)).into_response()
+        let mut response = (status, extra_headers, Json(error_response)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_and_ban_errors_keep_legacy_types() {
+        assert_eq!(ErrorCode::InvalidAuth.error_type(), "auth_error");
+        assert_eq!(ErrorCode::IpBanned.error_type(), "ip_banned");
+        assert_eq!(ErrorCode::ModelNotAllowed.error_type(), "permission_error");
+    }
+}
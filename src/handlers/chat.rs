@@ -1,20 +1,23 @@
 use axum::{
-    extract::State,
+    extract::{rejection::JsonRejection, State},
     http::HeaderMap,
     response::{IntoResponse, Json, Response},
-    Json as AxumJson,
+    Extension, Json as AxumJson,
 };
 use serde_json::{json, Value};
 
 use crate::error::{AppError, AppResult};
+use crate::middleware::ClientIdentity;
 use crate::services::ai::{AIService, EndpointType};
 use crate::state::AppState;
 
 pub async fn chat_completions(
     State(app_state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
     headers: HeaderMap,
-    AxumJson(payload): AxumJson<Value>,
+    payload: Result<AxumJson<Value>, JsonRejection>,
 ) -> AppResult<Response> {
+    let AxumJson(payload) = payload?;
     let ai_service = AIService::new(app_state);
 
     // 从JSON中提取model字段
@@ -28,16 +31,24 @@ pub async fn chat_completions(
     };
 
     // 直接转发请求，只替换model字段
-    ai_service
-        .forward_request_with_model_replacement(payload, model, headers, EndpointType::Completions)
-        .await
+    Ok(ai_service
+        .forward_request_with_model_replacement(
+            payload,
+            model,
+            headers,
+            client,
+            EndpointType::Completions,
+        )
+        .await)
 }
 
 pub async fn embeddings(
     State(app_state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
     headers: HeaderMap,
-    AxumJson(payload): AxumJson<Value>,
+    payload: Result<AxumJson<Value>, JsonRejection>,
 ) -> AppResult<Response> {
+    let AxumJson(payload) = payload?;
     let ai_service = AIService::new(app_state);
 
     // 从JSON中提取model字段
@@ -51,18 +62,32 @@ pub async fn embeddings(
     };
 
     // 直接转发请求，只替换model字段
-    ai_service
-        .forward_request_with_model_replacement(payload, model, headers, EndpointType::Embeddings)
-        .await
+    Ok(ai_service
+        .forward_request_with_model_replacement(
+            payload,
+            model,
+            headers,
+            client,
+            EndpointType::Embeddings,
+        )
+        .await)
 }
 
-pub async fn list_models(State(app_state): State<AppState>) -> impl IntoResponse {
+pub async fn list_models(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+) -> impl IntoResponse {
     let config = app_state.config.read().await;
+    // 只列出该客户端可以访问的模型
     let models: Vec<Value> = config
         .providers
         .iter()
-        .flat_map(|provider| &provider.models)
-        .map(|model| {
+        .flat_map(|provider| provider.models.iter().map(move |model| (provider, model)))
+        .filter(|(provider, model)| {
+            client.model_acl.allows(&model.alias)
+                && client.model_acl.allows_target(&provider.name, &model.model)
+        })
+        .map(|(_, model)| {
             json!({
                 "id": model.alias,
                 "object": "model",
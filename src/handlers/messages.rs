@@ -0,0 +1,66 @@
+use axum::{
+    body::to_bytes,
+    extract::{rejection::JsonRejection, State},
+    http::{header::CONTENT_LENGTH, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json as AxumJson,
+};
+use serde_json::Value;
+
+use crate::error::{AppError, AppResult};
+use crate::middleware::ClientIdentity;
+use crate::services::adapters::anthropic_inbound;
+use crate::services::ai::{AIService, EndpointType};
+use crate::state::AppState;
+
+/// Anthropic Messages 协议入口
+///
+/// Anthropic 提供者原样接收请求并返回响应，其他提供者的请求和响应在转发时与 OpenAI 格式互相转换
+pub async fn messages(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
+    payload: Result<AxumJson<Value>, JsonRejection>,
+) -> AppResult<Response> {
+    let payload = match payload {
+        Ok(AxumJson(payload)) => payload,
+        Err(e) => return Ok(anthropic_error(AppError::from(e).into_response()).await),
+    };
+    let ai_service = AIService::new(app_state);
+
+    // 从JSON中提取model字段
+    let model = match payload.get("model").and_then(|v| v.as_str()) {
+        Some(model) => model.to_string(),
+        None => {
+            return Ok(anthropic_error(
+                AppError::Validation("Missing or invalid model field".to_string()).into_response(),
+            )
+            .await);
+        }
+    };
+
+    let response = ai_service
+        .forward_request_with_model_replacement(
+            payload,
+            model,
+            headers,
+            client,
+            EndpointType::Messages,
+        )
+        .await;
+    if !response.status().is_success() {
+        return Ok(anthropic_error(response).await);
+    }
+    Ok(response)
+}
+
+/// 将 OpenAI 格式的错误响应转换为 Anthropic 错误格式，保留状态码
+async fn anthropic_error(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let value: Value = serde_json::from_slice(&body).unwrap_or_default();
+
+    parts.headers.remove(CONTENT_LENGTH);
+    let converted = anthropic_inbound::convert_error(parts.status.as_u16(), &value);
+    (parts, AxumJson(converted)).into_response()
+}
@@ -0,0 +1,104 @@
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashSet;
+
+use crate::services::key_health::KeyHealth;
+use crate::services::metrics::Exposition;
+use crate::services::persistence::key_fingerprint;
+use crate::state::AppState;
+
+/// Prometheus 文本格式的指标
+pub async fn get_metrics(State(app_state): State<AppState>) -> Response {
+    let mut out = Exposition::new();
+    app_state.metrics.render(&mut out);
+
+    // 转发目标的在途请求数，目标标识格式为 provider:model
+    out.family(
+        "ai_forward_in_flight_requests",
+        "gauge",
+        "Requests currently being forwarded, by provider and upstream model.",
+    );
+    for (target, count) in app_state.load_balancer.in_flight_snapshot() {
+        let (provider, model) = target.split_once(':').unwrap_or((&target, ""));
+        out.sample(
+            "ai_forward_in_flight_requests",
+            &[("provider", provider), ("model", model)],
+            count,
+        );
+    }
+
+    // 密钥健康状态，每个密钥只有当前状态的样本为 1，脱敏后的密钥可能重复，因此使用密钥指纹
+    out.family(
+        "ai_forward_key_status",
+        "gauge",
+        "Current health of each upstream API key.",
+    );
+    {
+        let config = app_state.config.read().await;
+        let mut seen = HashSet::new();
+        for provider in &config.providers {
+            for key in &provider.keys {
+                let fingerprint = key_fingerprint(key);
+                if !seen.insert((provider.name.as_str(), fingerprint.clone())) {
+                    continue;
+                }
+                let status = match app_state.key_health.health(key) {
+                    KeyHealth::Healthy => "healthy",
+                    KeyHealth::RateLimited { .. } => "rate_limited",
+                    KeyHealth::Quarantined { .. } => "quarantined",
+                    KeyHealth::Disabled { .. } => "disabled",
+                };
+                for state in ["healthy", "rate_limited", "quarantined", "disabled"] {
+                    out.sample(
+                        "ai_forward_key_status",
+                        &[
+                            ("provider", &provider.name),
+                            ("key", &fingerprint),
+                            ("status", state),
+                        ],
+                        u8::from(state == status),
+                    );
+                }
+            }
+        }
+    }
+
+    out.family(
+        "ai_forward_upstream_timeouts_total",
+        "counter",
+        "Upstream timeouts by provider and kind.",
+    );
+    for (provider, counts) in app_state.timeout_stats.snapshot() {
+        for (kind, count) in [
+            ("total", counts.total),
+            ("first_byte", counts.first_byte),
+            ("idle", counts.idle),
+        ] {
+            out.sample(
+                "ai_forward_upstream_timeouts_total",
+                &[("provider", &provider), ("kind", kind)],
+                count,
+            );
+        }
+    }
+
+    out.family(
+        "ai_forward_banned_ips",
+        "gauge",
+        "Banned IPv4 addresses and IPv6 /48 networks.",
+    );
+    out.sample(
+        "ai_forward_banned_ips",
+        &[],
+        app_state.ip_ban_manager.banned_count(),
+    );
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        out.finish(),
+    )
+        .into_response()
+}
@@ -1,3 +1,6 @@
 pub mod chat;
+pub mod messages;
+pub mod metrics;
+pub mod quota;
 pub mod stats;
 pub mod version;
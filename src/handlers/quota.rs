@@ -0,0 +1,116 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::config::QuotaConfig;
+use crate::error::{AppError, AppResult};
+use crate::services::quota::QuotaPeriod;
+use crate::state::AppState;
+
+/// 单次追加的 token 额度上限
+const MAX_TOP_UP_TOKENS: u64 = 1_000_000_000_000;
+/// 单次追加的费用额度上限，单位为美元
+const MAX_TOP_UP_COST: f64 = 1_000_000.0;
+
+/// 追加额度的请求体
+#[derive(Debug, Deserialize)]
+pub struct TopUpRequest {
+    pub period: QuotaPeriod,
+    #[serde(default)]
+    pub tokens: u64,
+    /// 追加的费用额度，单位为美元
+    #[serde(default)]
+    pub cost: f64,
+}
+
+/// 清零用量的查询参数，未指定周期时清零全部
+#[derive(Debug, Deserialize)]
+pub struct ResetQuery {
+    pub period: Option<QuotaPeriod>,
+}
+
+/// 所有配置了配额的客户端的配额状态
+pub async fn get_quotas(State(app_state): State<AppState>) -> Response {
+    let config = app_state.config.read().await;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "quotas": app_state.quota_manager.snapshot_all(&config.clients)
+        })),
+    )
+        .into_response()
+}
+
+/// 为客户端当前周期追加额度
+pub async fn top_up_quota(
+    State(app_state): State<AppState>,
+    Path(client): Path<String>,
+    payload: Result<Json<TopUpRequest>, JsonRejection>,
+) -> AppResult<Response> {
+    let Json(request) = payload?;
+    if !request.cost.is_finite() || request.cost < 0.0 {
+        return Err(AppError::Validation(
+            "cost must be a non-negative number".to_string(),
+        ));
+    }
+    if request.tokens > MAX_TOP_UP_TOKENS {
+        return Err(AppError::Validation(format!(
+            "tokens must not exceed {}",
+            MAX_TOP_UP_TOKENS
+        )));
+    }
+    if request.cost > MAX_TOP_UP_COST {
+        return Err(AppError::Validation(format!(
+            "cost must not exceed {}",
+            MAX_TOP_UP_COST
+        )));
+    }
+
+    let quota = client_quota(&app_state, &client).await?;
+    app_state
+        .quota_manager
+        .top_up(&client, request.period, request.tokens, request.cost);
+    info!(
+        "Topped up {:?} quota of client '{}' by {} tokens and ${}",
+        request.period, client, request.tokens, request.cost
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(app_state.quota_manager.snapshot(&client, &quota)),
+    )
+        .into_response())
+}
+
+/// 清零客户端当前周期的用量和追加额度
+pub async fn reset_quota(
+    State(app_state): State<AppState>,
+    Path(client): Path<String>,
+    Query(query): Query<ResetQuery>,
+) -> AppResult<Response> {
+    let quota = client_quota(&app_state, &client).await?;
+    app_state.quota_manager.reset(&client, query.period);
+    info!("Reset quota usage of client '{}'", client);
+
+    Ok((
+        StatusCode::OK,
+        Json(app_state.quota_manager.snapshot(&client, &quota)),
+    )
+        .into_response())
+}
+
+/// 查找客户端的配额设置
+async fn client_quota(app_state: &AppState, client: &str) -> AppResult<QuotaConfig> {
+    let config = app_state.config.read().await;
+    config
+        .clients
+        .iter()
+        .find(|c| c.name == client)
+        .and_then(|c| c.quota)
+        .ok_or_else(|| AppError::Validation(format!("Client '{}' has no quota configured", client)))
+}
@@ -20,30 +20,15 @@ pub async fn get_stats(State(app_state): State<AppState>) -> impl IntoResponse {
             })),
         )
             .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "error": {
-                    "message": e.to_string(),
-                    "type": "service_error"
-                }
-            })),
-        )
-            .into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
 pub async fn reset_stats(State(app_state): State<AppState>) -> AppResult<Response> {
-    // 重置统计信息
-    {
-        let provider_usage = app_state.provider_usage.write().await;
-        provider_usage.clear();
-    }
-
-    {
-        let key_usage = app_state.key_usage.write().await;
-        key_usage.clear();
-    }
+    // 请求次数和 token 用量是持久化的历史数据，不在这里清零；
+    // 只重置运行时状态，被禁用的密钥重新参与选择
+    app_state.key_health.reset();
+    app_state.timeout_stats.reset();
 
     // 重新读取配置文件
     {
@@ -52,7 +37,7 @@ pub async fn reset_stats(State(app_state): State<AppState>) -> AppResult<Respons
 
     Ok((
         StatusCode::OK,
-        Json(json!({"message": "stats reset and config reloaded"})),
+        Json(json!({"message": "key health reset and config reloaded"})),
     )
         .into_response())
 }
@@ -1,7 +1,27 @@
+use file_rotate::{
+    compression::Compression,
+    suffix::{AppendTimestamp, FileLimit},
+    ContentLimit, FileRotate,
+};
 use tracing_subscriber::{prelude::*, Layer};
 
 use crate::config::Config;
 
+/// 按大小轮转的记录文件，默认保留 10 个文件，单个文件 50MB，用于运行日志、审计日志和内容捕获
+pub fn rotating_file(
+    file: &str,
+    max_files: Option<usize>,
+    max_file_size: Option<u64>,
+) -> FileRotate<AppendTimestamp> {
+    FileRotate::new(
+        file,
+        AppendTimestamp::default(FileLimit::MaxFiles(max_files.unwrap_or(10))),
+        ContentLimit::BytesSurpassed(max_file_size.unwrap_or(50 * 1024 * 1024) as usize),
+        Compression::OnRotate(1),
+        None,
+    )
+}
+
 pub async fn init_logging(config: &Config) {
     let level = config
         .log
@@ -29,8 +49,6 @@ pub async fn init_logging(config: &Config) {
     if let Some(log) = &config.log {
         let log = log.clone();
 
-        use file_rotate::{compression::*, suffix::*, *};
-
         let file_layer = tracing_subscriber::fmt::layer()
             .compact()
             .with_ansi(false)
@@ -38,18 +56,7 @@ pub async fn init_logging(config: &Config) {
             .with_timer(tracing_subscriber::fmt::time::ChronoLocal::new(
                 String::from("%Y-%m-%d %H:%M:%S"),
             ))
-            .with_writer(move || {
-                let log_file = log.file.clone();
-                FileRotate::new(
-                    log_file,
-                    AppendTimestamp::default(FileLimit::MaxFiles(log.max_files.unwrap_or(3))),
-                    ContentLimit::BytesSurpassed(
-                        log.max_file_size.unwrap_or(10 * 1024 * 1024) as usize
-                    ),
-                    Compression::OnRotate(1),
-                    None,
-                )
-            })
+            .with_writer(move || rotating_file(&log.file, log.max_files, log.max_file_size))
             .with_filter(level)
             .boxed();
         layers.push(file_layer);
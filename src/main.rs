@@ -19,8 +19,8 @@ mod services;
 mod state;
 
 use config::Config;
-use handlers::{chat, stats, version};
-use middleware::auth_handler;
+use handlers::{chat, messages, metrics, quota, stats, version};
+use middleware::{admin_auth_handler, auth_handler};
 use state::AppState;
 
 #[derive(Parser, Debug)]
@@ -49,7 +49,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Application initialized successfully");
 
     // 创建路由
-    let app = create_router(app_state);
+    let app = create_router(app_state.clone());
 
     // 启动服务器
     let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
@@ -64,6 +64,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     server.await?;
 
+    // 停止前写入统计数据
+    app_state.flush_usage().await;
+
     Ok(())
 }
 
@@ -73,6 +76,7 @@ fn create_router(app_state: AppState) -> Router {
         Router::new()
             .route("/chat/completions", post(chat::chat_completions))
             .route("/embeddings", post(chat::embeddings))
+            .route("/messages", post(messages::messages))
             .route("/models", get(chat::list_models))
             .layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
@@ -83,11 +87,23 @@ fn create_router(app_state: AppState) -> Router {
     let manage_routes = Router::new()
         .route("/stats", get(stats::get_stats))
         .route("/reset", get(stats::reset_stats))
+        .route("/metrics", get(metrics::get_metrics))
         .route("/version", get(version::get_version));
 
+    // 配额接口可以追加额度，需要管理令牌
+    let admin_routes = Router::new()
+        .route("/quotas", get(quota::get_quotas))
+        .route("/quotas/{client}/top_up", post(quota::top_up_quota))
+        .route("/quotas/{client}/reset", post(quota::reset_quota))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            admin_auth_handler,
+        ));
+
     Router::new()
         .merge(ai_routes)
         .merge(manage_routes)
+        .merge(admin_routes)
         .layer(
             ServiceBuilder::new().layer(
                 TraceLayer::new_for_http()
@@ -1,19 +1,34 @@
 use axum::{
     extract::{ConnectInfo, Request, State},
-    http::StatusCode,
+    http::HeaderMap,
     middleware::Next,
-    response::{IntoResponse, Json, Response},
+    response::{IntoResponse, Response},
 };
-use serde_json::json;
 use std::net::{IpAddr, SocketAddr};
 use tracing::warn;
 
+use crate::config::RateLimitConfig;
+use crate::error::AppError;
+use crate::services::acl::ModelAcl;
 use crate::state::AppState;
 
+/// 通过认证的客户端，由认证中间件放入请求扩展中
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// 客户端名称，用于用量统计
+    pub name: String,
+    /// 模型访问控制列表
+    pub model_acl: ModelAcl,
+    /// 该客户端的限流设置，在转发请求时与全局和模型别名的限流一起检查
+    pub rate_limit: Option<RateLimitConfig>,
+    /// 客户端真实 IP
+    pub ip: String,
+}
+
 pub async fn auth_handler(
     State(app_state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    req: Request,
+    mut req: Request,
     next: Next,
 ) -> Response {
     // 获取客户端真实IP，优先级：X-Real-IP > X-Forwarded-For > 连接地址
@@ -22,46 +37,116 @@ pub async fn auth_handler(
     // 检查IP是否已被封禁
     if app_state.ip_ban_manager.is_banned(&client_ip) {
         warn!("Blocked banned IP: {}", client_ip);
-        let error_response = Json(json!({
-            "error": {
-                "message": "Your IP has been permanently banned due to multiple failed authentication attempts",
-                "type": "ip_banned"
-            }
-        }));
-        return (StatusCode::FORBIDDEN, error_response).into_response();
+        return AppError::IpBanned(
+            "Your IP has been permanently banned due to multiple failed authentication attempts"
+                .to_string(),
+        )
+        .into_response();
     }
 
-    let auth_header = req.headers().get("authorization");
-
-    if let Some(auth_header) = auth_header {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                let config = app_state.config.read().await;
-                if token == config.auth {
-                    // 认证成功，重置该IP的失败次数
-                    app_state.ip_ban_manager.reset_failures(&client_ip);
-                    return next.run(req).await;
-                }
+    let client = match extract_token(req.headers()) {
+        Some(token) => app_state.config.read().await.find_client(token),
+        None => None,
+    };
+
+    if let Some(client) = client {
+        // 已吊销或过期的密钥属于有效凭证，不计入失败次数
+        if !client.is_enabled() {
+            warn!("Rejected request from disabled client '{}'", client.name);
+            return AppError::Unauthorized("API key is disabled".to_string()).into_response();
+        }
+        if client.is_expired() {
+            warn!("Rejected request from expired client '{}'", client.name);
+            return AppError::Unauthorized("API key has expired".to_string()).into_response();
+        }
+
+        // 配额用尽后拒绝请求，直到周期结束或管理员追加额度
+        if let Some(quota) = &client.quota {
+            if let Err(message) = app_state.quota_manager.check(&client.name, quota) {
+                warn!(
+                    "Rejected request from client '{}': quota exhausted",
+                    client.name
+                );
+                return AppError::InsufficientQuota(message).into_response();
             }
         }
+
+        // 认证成功，重置该IP的失败次数
+        app_state.ip_ban_manager.reset_failures(&client_ip);
+
+        req.extensions_mut().insert(ClientIdentity {
+            name: client.name,
+            model_acl: ModelAcl {
+                allow: client.models,
+                deny: client.deny_models,
+            },
+            rate_limit: client.rate_limit,
+            ip: client_ip,
+        });
+        return next.run(req).await;
     }
 
     // 认证失败，记录失败次数
     app_state.ip_ban_manager.record_failure(&client_ip);
+    app_state.metrics.record_auth_failure();
     warn!(
         "Unauthorized request from IP: {}, failure count: {}",
         client_ip,
         app_state.ip_ban_manager.get_failure_count(&client_ip)
     );
 
-    let error_response = Json(json!({
-        "error": {
-            "message": "Invalid authorization token",
-            "type": "auth_error"
+    AppError::Unauthorized("Invalid authorization token".to_string()).into_response()
+}
+
+/// 管理接口认证，令牌必须与配置中的 `admin_key` 一致，失败次数与客户端认证共同计入 IP 封禁
+pub async fn admin_auth_handler(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client_ip = extract_client_ip(&req, &addr);
+
+    if app_state.ip_ban_manager.is_banned(&client_ip) {
+        warn!("Blocked banned IP: {}", client_ip);
+        return AppError::IpBanned(
+            "Your IP has been permanently banned due to multiple failed authentication attempts"
+                .to_string(),
+        )
+        .into_response();
+    }
+
+    let authorized = {
+        let config = app_state.config.read().await;
+        match (config.admin_key.as_deref(), extract_token(req.headers())) {
+            (Some(admin_key), Some(token)) => admin_key == token,
+            _ => false,
         }
-    }));
+    };
+
+    if authorized {
+        app_state.ip_ban_manager.reset_failures(&client_ip);
+        return next.run(req).await;
+    }
 
-    (StatusCode::UNAUTHORIZED, error_response).into_response()
+    app_state.ip_ban_manager.record_failure(&client_ip);
+    app_state.metrics.record_auth_failure();
+    warn!(
+        "Unauthorized admin request from IP: {}, failure count: {}",
+        client_ip,
+        app_state.ip_ban_manager.get_failure_count(&client_ip)
+    );
+
+    AppError::Unauthorized("Invalid admin token".to_string()).into_response()
+}
+
+/// 提取客户端令牌，支持 `Authorization: Bearer` 和 Anthropic 客户端使用的 `x-api-key`
+pub(crate) fn extract_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|h| h.to_str().ok()))
 }
 
 /// 判断是否为内网IP地址
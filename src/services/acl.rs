@@ -0,0 +1,124 @@
+/// 模型访问控制列表，按客户端请求的模型名称匹配，支持模型别名和 `provider:model` 形式
+///
+/// 模式支持 `*`（任意多个字符）和 `?`（单个字符）通配符。禁止列表还会匹配
+/// `provider:model` 请求中的模型名称，以及每个转发目标的 `provider:model` 和上游模型名称，
+/// 避免绕过别名直接访问被禁止的模型
+#[derive(Debug, Clone, Default)]
+pub struct ModelAcl {
+    /// 允许访问的模型，为空时允许全部
+    pub allow: Vec<String>,
+    /// 禁止访问的模型，优先于 `allow`
+    pub deny: Vec<String>,
+}
+
+impl ModelAcl {
+    /// 检查客户端请求的模型名称
+    pub fn allows(&self, model: &str) -> bool {
+        let allowed = self.allow.is_empty() || matches_any(&self.allow, model);
+        let denied = match model.split_once(':') {
+            Some((_, upstream)) => self.denies(model, upstream),
+            None => matches_any(&self.deny, model),
+        };
+        allowed && !denied
+    }
+
+    /// 检查解析出的转发目标，匹配 `provider:model` 和上游模型名称
+    pub fn allows_target(&self, provider: &str, model: &str) -> bool {
+        !self.denies(&format!("{}:{}", provider, model), model)
+    }
+
+    fn denies(&self, target: &str, model: &str) -> bool {
+        matches_any(&self.deny, target) || matches_any(&self.deny, model)
+    }
+}
+
+fn matches_any(patterns: &[String], text: &str) -> bool {
+    patterns.iter().any(|p| glob_match(p, text))
+}
+
+/// 通配符匹配，`*` 匹配任意多个字符，`?` 匹配单个字符
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 `*` 的位置，以及它开始匹配的文本位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // 回溯，让 `*` 多匹配一个字符
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(allow: &[&str], deny: &[&str]) -> ModelAcl {
+        ModelAcl {
+            allow: allow.iter().map(|p| p.to_string()).collect(),
+            deny: deny.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("gpt-*", "gpt-4o"));
+        assert!(glob_match("gpt-*", "gpt-"));
+        assert!(glob_match("cl?ude", "claude"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("openai:*-mini", "openai:gpt-4o-mini"));
+        assert!(!glob_match("cl?ude", "clude"));
+        assert!(!glob_match("gpt-*", "chatgpt-4"));
+        assert!(!glob_match("a*b", "axxbc"));
+    }
+
+    #[test]
+    fn empty_allow_list_allows_everything_not_denied() {
+        let acl = acl(&[], &["gpt-3.5-*"]);
+        assert!(acl.allows("gpt-4"));
+        assert!(acl.allows("claude"));
+        assert!(!acl.allows("gpt-3.5-turbo"));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let acl = acl(&["gpt-*"], &["gpt-4o*"]);
+        assert!(acl.allows("gpt-4"));
+        assert!(!acl.allows("gpt-4o-mini"));
+        assert!(!acl.allows("claude"));
+    }
+
+    #[test]
+    fn deny_applies_to_model_part_of_provider_requests() {
+        let acl = acl(&["openai:*"], &["gpt-3.5-*"]);
+        assert!(acl.allows("openai:gpt-4"));
+        assert!(!acl.allows("openai:gpt-3.5-turbo"));
+        // 允许列表只匹配请求的完整名称
+        assert!(!acl.allows("gpt-4"));
+    }
+
+    #[test]
+    fn deny_applies_to_resolved_targets() {
+        let acl = acl(&["fast"], &["gpt-3.5-*", "cheap:*"]);
+        assert!(acl.allows_target("openai", "gpt-4"));
+        assert!(!acl.allows_target("openai", "gpt-3.5-turbo"));
+        assert!(!acl.allows_target("cheap", "gpt-4"));
+    }
+}
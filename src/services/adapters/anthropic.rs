@@ -0,0 +1,539 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::{content_text, parse_data_url, ResponseOptions, SseEvent, SseTranslator};
+
+/// 默认的 `anthropic-version` 请求头
+pub const DEFAULT_VERSION: &str = "2023-06-01";
+
+/// 请求没有指定 `max_tokens` 时使用的默认值，Anthropic 要求必须提供
+const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// 将 OpenAI chat completions 请求转换为 Anthropic messages 请求
+pub fn convert_request(model: &str, payload: &Value) -> Value {
+    let mut system_parts = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+
+    for message in payload["messages"].as_array().into_iter().flatten() {
+        match message["role"].as_str().unwrap_or("user") {
+            "system" | "developer" => system_parts.push(content_text(&message["content"])),
+            "assistant" => {
+                let mut blocks = content_blocks(&message["content"]);
+                for call in message["tool_calls"].as_array().into_iter().flatten() {
+                    let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call["id"],
+                        "name": call["function"]["name"],
+                        "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+                    }));
+                }
+                push_message(&mut messages, "assistant", blocks);
+            }
+            "tool" => {
+                let result = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message["tool_call_id"],
+                    "content": content_text(&message["content"]),
+                });
+                push_message(&mut messages, "user", vec![result]);
+            }
+            _ => push_message(&mut messages, "user", content_blocks(&message["content"])),
+        }
+    }
+
+    let mut body = Map::new();
+    body.insert("model".to_string(), json!(model));
+    body.insert("messages".to_string(), json!(messages));
+
+    let system = system_parts
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if !system.is_empty() {
+        body.insert("system".to_string(), json!(system));
+    }
+
+    let max_tokens = payload["max_tokens"]
+        .as_u64()
+        .or_else(|| payload["max_completion_tokens"].as_u64())
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+    body.insert("max_tokens".to_string(), json!(max_tokens));
+
+    for field in ["temperature", "top_p", "top_k", "stream"] {
+        if !payload[field].is_null() {
+            body.insert(field.to_string(), payload[field].clone());
+        }
+    }
+
+    match &payload["stop"] {
+        Value::String(stop) => {
+            body.insert("stop_sequences".to_string(), json!([stop]));
+        }
+        Value::Array(stops) => {
+            body.insert("stop_sequences".to_string(), json!(stops));
+        }
+        _ => {}
+    }
+
+    if let Some(tools) = payload["tools"].as_array() {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter(|t| t["type"] == "function")
+            .map(|t| {
+                let function = &t["function"];
+                let mut tool = json!({
+                    "name": function["name"],
+                    "input_schema": if function["parameters"].is_null() {
+                        json!({"type": "object", "properties": {}})
+                    } else {
+                        function["parameters"].clone()
+                    },
+                });
+                if let Some(description) = function["description"].as_str() {
+                    tool["description"] = json!(description);
+                }
+                tool
+            })
+            .collect();
+        if !tools.is_empty() {
+            body.insert("tools".to_string(), json!(tools));
+        }
+    }
+
+    let tool_choice = match &payload["tool_choice"] {
+        Value::String(choice) => match choice.as_str() {
+            "auto" => Some(json!({"type": "auto"})),
+            "required" => Some(json!({"type": "any"})),
+            "none" => Some(json!({"type": "none"})),
+            _ => None,
+        },
+        Value::Object(choice) => choice
+            .get("function")
+            .and_then(|f| f["name"].as_str())
+            .map(|name| json!({"type": "tool", "name": name})),
+        _ => None,
+    };
+    if let Some(mut tool_choice) = tool_choice {
+        if payload["parallel_tool_calls"] == false && tool_choice["type"] != "none" {
+            tool_choice["disable_parallel_tool_use"] = json!(true);
+        }
+        body.insert("tool_choice".to_string(), tool_choice);
+    }
+
+    if let Some(user) = payload["user"].as_str() {
+        body.insert("metadata".to_string(), json!({"user_id": user}));
+    }
+
+    Value::Object(body)
+}
+
+/// 追加消息，Anthropic 要求 user/assistant 交替出现，相同角色的连续消息合并为一条
+fn push_message(messages: &mut Vec<Value>, role: &str, blocks: Vec<Value>) {
+    if blocks.is_empty() {
+        return;
+    }
+
+    if let Some(last) = messages.last_mut() {
+        if last["role"] == role {
+            if let Some(content) = last["content"].as_array_mut() {
+                content.extend(blocks);
+                return;
+            }
+        }
+    }
+
+    messages.push(json!({"role": role, "content": blocks}));
+}
+
+/// 将 OpenAI 消息内容转换为 Anthropic 内容块
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) if !text.is_empty() => vec![json!({"type": "text", "text": text})],
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part["type"].as_str() {
+                Some("text") => part["text"]
+                    .as_str()
+                    .filter(|t| !t.is_empty())
+                    .map(|text| json!({"type": "text", "text": text})),
+                Some("image_url") => part["image_url"]["url"].as_str().map(image_block),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 将图片 URL 转换为图片内容块，支持 data URL 和普通 URL
+fn image_block(url: &str) -> Value {
+    match parse_data_url(url) {
+        Some((media_type, data)) => json!({
+            "type": "image",
+            "source": {"type": "base64", "media_type": media_type, "data": data},
+        }),
+        None => json!({"type": "image", "source": {"type": "url", "url": url}}),
+    }
+}
+
+/// 转换停止原因
+fn finish_reason(stop_reason: &Value) -> Value {
+    match stop_reason.as_str() {
+        Some("end_turn") | Some("stop_sequence") => json!("stop"),
+        Some("max_tokens") => json!("length"),
+        Some("tool_use") => json!("tool_calls"),
+        Some("refusal") => json!("content_filter"),
+        Some(other) => json!(other),
+        None => Value::Null,
+    }
+}
+
+/// 转换用量统计，缓存命中和写入的 token 都计入 prompt_tokens
+pub fn convert_usage(usage: &Value) -> Value {
+    let cached = usage["cache_read_input_tokens"].as_u64().unwrap_or(0);
+    let prompt = usage["input_tokens"].as_u64().unwrap_or(0)
+        + cached
+        + usage["cache_creation_input_tokens"].as_u64().unwrap_or(0);
+    let completion = usage["output_tokens"].as_u64().unwrap_or(0);
+
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+        "prompt_tokens_details": {"cached_tokens": cached},
+    })
+}
+
+/// 将 Anthropic messages 响应转换为 OpenAI chat completion 响应
+pub fn convert_response(body: &Value) -> Value {
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    let mut tool_calls = Vec::new();
+
+    for block in body["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+            Some("thinking") => reasoning.push_str(block["thinking"].as_str().unwrap_or_default()),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block["input"].to_string(),
+                },
+            })),
+            _ => {}
+        }
+    }
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { json!(text) },
+    });
+    if !reasoning.is_empty() {
+        message["reasoning_content"] = json!(reasoning);
+    }
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = json!(tool_calls);
+    }
+
+    json!({
+        "id": body["id"],
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": body["model"],
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason(&body["stop_reason"]),
+        }],
+        "usage": convert_usage(&body["usage"]),
+    })
+}
+
+/// 将 Anthropic 流式事件转换为 OpenAI `chat.completion.chunk`
+pub struct StreamTranslator {
+    options: ResponseOptions,
+    id: String,
+    model: String,
+    created: i64,
+    /// Anthropic 内容块索引 -> OpenAI tool_calls 索引
+    tool_indexes: HashMap<u64, usize>,
+    /// 累计的用量，`message_start` 给出输入部分，`message_delta` 给出输出部分
+    usage: Map<String, Value>,
+}
+
+impl StreamTranslator {
+    pub fn new(options: ResponseOptions) -> Self {
+        Self {
+            options,
+            id: String::new(),
+            model: String::new(),
+            created: chrono::Utc::now().timestamp(),
+            tool_indexes: HashMap::new(),
+            usage: Map::new(),
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Value) -> SseEvent {
+        SseEvent::data(json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        }))
+    }
+
+    fn merge_usage(&mut self, usage: &Value) {
+        if let Some(usage) = usage.as_object() {
+            for (key, value) in usage {
+                if !value.is_null() {
+                    self.usage.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+impl SseTranslator for StreamTranslator {
+    fn translate(&mut self, event: &str, data: &str) -> Vec<SseEvent> {
+        let Ok(data) = serde_json::from_str::<Value>(data) else {
+            return Vec::new();
+        };
+        let event = if event == "message" {
+            data["type"].as_str().unwrap_or_default()
+        } else {
+            event
+        };
+
+        match event {
+            "message_start" => {
+                let message = &data["message"];
+                self.id = message["id"].as_str().unwrap_or_default().to_string();
+                self.model = message["model"].as_str().unwrap_or_default().to_string();
+                self.merge_usage(&message["usage"]);
+                vec![self.chunk(json!({"role": "assistant", "content": ""}), Value::Null)]
+            }
+            "content_block_start" => {
+                let block = &data["content_block"];
+                if block["type"] != "tool_use" {
+                    return Vec::new();
+                }
+                let tool_index = self.tool_indexes.len();
+                self.tool_indexes
+                    .insert(data["index"].as_u64().unwrap_or(0), tool_index);
+                vec![self.chunk(
+                    json!({"tool_calls": [{
+                        "index": tool_index,
+                        "id": block["id"],
+                        "type": "function",
+                        "function": {"name": block["name"], "arguments": ""},
+                    }]}),
+                    Value::Null,
+                )]
+            }
+            "content_block_delta" => {
+                let delta = &data["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => {
+                        vec![self.chunk(json!({"content": delta["text"]}), Value::Null)]
+                    }
+                    Some("thinking_delta") => {
+                        vec![self
+                            .chunk(json!({"reasoning_content": delta["thinking"]}), Value::Null)]
+                    }
+                    Some("input_json_delta") => {
+                        let block_index = data["index"].as_u64().unwrap_or(0);
+                        let Some(&tool_index) = self.tool_indexes.get(&block_index) else {
+                            return Vec::new();
+                        };
+                        vec![self.chunk(
+                            json!({"tool_calls": [{
+                                "index": tool_index,
+                                "function": {"arguments": delta["partial_json"]},
+                            }]}),
+                            Value::Null,
+                        )]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            "message_delta" => {
+                self.merge_usage(&data["usage"]);
+                vec![self.chunk(json!({}), finish_reason(&data["delta"]["stop_reason"]))]
+            }
+            "message_stop" => {
+                let mut output = Vec::new();
+                if self.options.include_usage {
+                    output.push(SseEvent::data(json!({
+                        "id": self.id,
+                        "object": "chat.completion.chunk",
+                        "created": self.created,
+                        "model": self.model,
+                        "choices": [],
+                        "usage": convert_usage(&Value::Object(self.usage.clone())),
+                    })));
+                }
+                output.push(SseEvent::data("[DONE]"));
+                output
+            }
+            "error" => vec![SseEvent::data(json!({"error": data["error"]}))],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(include_usage: bool) -> ResponseOptions {
+        ResponseOptions {
+            stream: true,
+            include_usage,
+        }
+    }
+
+    fn chunks(events: Vec<SseEvent>) -> Vec<Value> {
+        events
+            .into_iter()
+            .map(|e| serde_json::from_str(&e.data).unwrap_or(Value::String(e.data)))
+            .collect()
+    }
+
+    #[test]
+    fn request_moves_system_and_merges_tool_results() {
+        let payload = json!({
+            "model": "claude",
+            "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "get_time", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "noon"}
+            ],
+            "stop": "END",
+            "tool_choice": "required",
+            "parallel_tool_calls": false
+        });
+
+        let body = convert_request("claude-x", &payload);
+        assert_eq!(body["model"], "claude-x");
+        assert_eq!(body["system"], "be brief");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["stop_sequences"], json!(["END"]));
+        assert_eq!(
+            body["tool_choice"],
+            json!({"type": "any", "disable_parallel_tool_use": true})
+        );
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"], json!({"city": "Paris"}));
+        // 连续的工具结果合并为同一条 user 消息
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[2]["content"][1]["tool_use_id"], "call_2");
+    }
+
+    #[test]
+    fn response_converts_content_and_usage() {
+        let body = json!({
+            "id": "msg_1",
+            "model": "claude-x",
+            "content": [
+                {"type": "thinking", "thinking": "hmm"},
+                {"type": "text", "text": "Hi"},
+                {"type": "tool_use", "id": "tu_1", "name": "f", "input": {"a": 1}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "cache_read_input_tokens": 5, "output_tokens": 3}
+        });
+
+        let response = convert_response(&body);
+        let message = &response["choices"][0]["message"];
+        assert_eq!(message["content"], "Hi");
+        assert_eq!(message["reasoning_content"], "hmm");
+        assert_eq!(
+            message["tool_calls"][0]["function"]["arguments"],
+            "{\"a\":1}"
+        );
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["prompt_tokens"], 15);
+        assert_eq!(response["usage"]["total_tokens"], 18);
+        assert_eq!(
+            response["usage"]["prompt_tokens_details"]["cached_tokens"],
+            5
+        );
+    }
+
+    #[test]
+    fn stream_translates_events_to_chunks() {
+        let mut translator = StreamTranslator::new(options(true));
+        let mut output = Vec::new();
+        for (event, data) in [
+            (
+                "message_start",
+                json!({"message": {"id": "msg_1", "model": "claude-x", "usage": {"input_tokens": 10, "output_tokens": 1}}}),
+            ),
+            (
+                "content_block_start",
+                json!({"index": 0, "content_block": {"type": "text", "text": ""}}),
+            ),
+            (
+                "content_block_delta",
+                json!({"index": 0, "delta": {"type": "text_delta", "text": "Hello"}}),
+            ),
+            (
+                "content_block_start",
+                json!({"index": 1, "content_block": {"type": "tool_use", "id": "tu_1", "name": "f"}}),
+            ),
+            (
+                "content_block_delta",
+                json!({"index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"a\":"}}),
+            ),
+            (
+                "message_delta",
+                json!({"delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 7}}),
+            ),
+            ("message_stop", json!({})),
+        ] {
+            output.extend(translator.translate(event, &data.to_string()));
+        }
+
+        let chunks = chunks(output);
+        assert_eq!(chunks.len(), 7);
+        assert_eq!(chunks[0]["id"], "msg_1");
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hello");
+        assert_eq!(
+            chunks[2]["choices"][0]["delta"]["tool_calls"][0]["index"],
+            0
+        );
+        assert_eq!(
+            chunks[3]["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"],
+            "{\"a\":"
+        );
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[5]["usage"]["prompt_tokens"], 10);
+        assert_eq!(chunks[5]["usage"]["completion_tokens"], 7);
+        assert_eq!(chunks[6], "[DONE]");
+    }
+
+    #[test]
+    fn stream_omits_usage_unless_requested() {
+        let mut translator = StreamTranslator::new(options(false));
+        let output = translator.translate("message_stop", "{}");
+        assert_eq!(chunks(output), vec![json!("[DONE]")]);
+    }
+}
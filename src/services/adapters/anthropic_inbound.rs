@@ -0,0 +1,563 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::{SseEvent, SseTranslator};
+
+/// 将客户端的 Anthropic messages 请求转换为 OpenAI chat completions 请求
+pub fn convert_request(payload: &Value) -> Value {
+    let mut messages = Vec::new();
+
+    match &payload["system"] {
+        Value::String(system) if !system.is_empty() => {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        Value::Array(blocks) => {
+            let system = blocks_text(blocks);
+            if !system.is_empty() {
+                messages.push(json!({"role": "system", "content": system}));
+            }
+        }
+        _ => {}
+    }
+
+    for message in payload["messages"].as_array().into_iter().flatten() {
+        let role = message["role"].as_str().unwrap_or("user");
+        let blocks = match &message["content"] {
+            Value::String(text) => {
+                messages.push(json!({"role": role, "content": text}));
+                continue;
+            }
+            Value::Array(blocks) => blocks,
+            _ => continue,
+        };
+
+        let mut parts = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => parts.push(json!({"type": "text", "text": block["text"]})),
+                Some("image") => {
+                    let source = &block["source"];
+                    let url = match source["type"].as_str() {
+                        Some("base64") => format!(
+                            "data:{};base64,{}",
+                            source["media_type"].as_str().unwrap_or("image/png"),
+                            source["data"].as_str().unwrap_or_default()
+                        ),
+                        _ => source["url"].as_str().unwrap_or_default().to_string(),
+                    };
+                    parts.push(json!({"type": "image_url", "image_url": {"url": url}}));
+                }
+                Some("tool_use") => tool_calls.push(json!({
+                    "id": block["id"],
+                    "type": "function",
+                    "function": {
+                        "name": block["name"],
+                        "arguments": block["input"].to_string(),
+                    },
+                })),
+                // 工具结果在 OpenAI 格式中是独立的 tool 消息，需要排在同一轮的其他内容之前
+                Some("tool_result") => {
+                    let content = match &block["content"] {
+                        Value::String(text) => text.clone(),
+                        Value::Array(blocks) => blocks_text(blocks),
+                        _ => String::new(),
+                    };
+                    messages.push(json!({
+                        "role": "tool",
+                        "tool_call_id": block["tool_use_id"],
+                        "content": content,
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        if role == "assistant" {
+            let text = blocks_text(blocks);
+            let mut message = json!({
+                "role": "assistant",
+                "content": if text.is_empty() { Value::Null } else { json!(text) },
+            });
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = json!(tool_calls);
+            }
+            if !text.is_empty() || !tool_calls.is_empty() {
+                messages.push(message);
+            }
+        } else if !parts.is_empty() {
+            messages.push(json!({"role": role, "content": parts}));
+        }
+    }
+
+    let mut body = Map::new();
+    body.insert("model".to_string(), payload["model"].clone());
+    body.insert("messages".to_string(), json!(messages));
+
+    for field in ["max_tokens", "temperature", "top_p", "stream"] {
+        if !payload[field].is_null() {
+            body.insert(field.to_string(), payload[field].clone());
+        }
+    }
+    if !payload["stop_sequences"].is_null() {
+        body.insert("stop".to_string(), payload["stop_sequences"].clone());
+    }
+    if let Some(user) = payload["metadata"]["user_id"].as_str() {
+        body.insert("user".to_string(), json!(user));
+    }
+
+    if let Some(tools) = payload["tools"].as_array() {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter(|t| !t["input_schema"].is_null())
+            .map(|t| {
+                let mut function = json!({
+                    "name": t["name"],
+                    "parameters": t["input_schema"],
+                });
+                if let Some(description) = t["description"].as_str() {
+                    function["description"] = json!(description);
+                }
+                json!({"type": "function", "function": function})
+            })
+            .collect();
+        if !tools.is_empty() {
+            body.insert("tools".to_string(), json!(tools));
+        }
+    }
+
+    let tool_choice = &payload["tool_choice"];
+    let converted = match tool_choice["type"].as_str() {
+        Some("auto") => Some(json!("auto")),
+        Some("any") => Some(json!("required")),
+        Some("none") => Some(json!("none")),
+        Some("tool") => {
+            Some(json!({"type": "function", "function": {"name": tool_choice["name"]}}))
+        }
+        _ => None,
+    };
+    if let Some(converted) = converted {
+        body.insert("tool_choice".to_string(), converted);
+    }
+    if tool_choice["disable_parallel_tool_use"] == true {
+        body.insert("parallel_tool_calls".to_string(), json!(false));
+    }
+
+    Value::Object(body)
+}
+
+/// 拼接内容块中的文本
+fn blocks_text(blocks: &[Value]) -> String {
+    blocks
+        .iter()
+        .filter(|b| b["type"] == "text")
+        .filter_map(|b| b["text"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 转换停止原因
+fn stop_reason(finish_reason: &Value) -> Value {
+    match finish_reason.as_str() {
+        Some("stop") => json!("end_turn"),
+        Some("length") => json!("max_tokens"),
+        Some("tool_calls") | Some("function_call") => json!("tool_use"),
+        Some("content_filter") => json!("refusal"),
+        Some(_) => json!("end_turn"),
+        None => Value::Null,
+    }
+}
+
+/// 转换用量统计，缓存命中的 token 单独列出
+fn convert_usage(usage: &Value) -> Value {
+    let cached = usage["prompt_tokens_details"]["cached_tokens"]
+        .as_u64()
+        .unwrap_or(0);
+    let prompt = usage["prompt_tokens"].as_u64().unwrap_or(0);
+
+    json!({
+        "input_tokens": prompt.saturating_sub(cached),
+        "output_tokens": usage["completion_tokens"].as_u64().unwrap_or(0),
+        "cache_read_input_tokens": cached,
+    })
+}
+
+/// 将 OpenAI chat completion 响应转换为 Anthropic messages 响应
+pub fn convert_response(body: &Value) -> Value {
+    let choice = &body["choices"][0];
+    let message = &choice["message"];
+    let mut content = Vec::new();
+
+    if let Some(reasoning) = message["reasoning_content"]
+        .as_str()
+        .filter(|r| !r.is_empty())
+    {
+        content.push(json!({"type": "thinking", "thinking": reasoning}));
+    }
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        content.push(json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+
+    json!({
+        "id": body["id"],
+        "type": "message",
+        "role": "assistant",
+        "model": body["model"],
+        "content": content,
+        "stop_reason": stop_reason(&choice["finish_reason"]),
+        "stop_sequence": null,
+        "usage": convert_usage(&body["usage"]),
+    })
+}
+
+/// 将上游错误响应转换为 Anthropic 错误格式
+pub fn convert_error(status: u16, body: &Value) -> Value {
+    let error_type = match status {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 => "overloaded_error",
+        _ => "api_error",
+    };
+    let message = body["error"]["message"]
+        .as_str()
+        .or_else(|| body["error"].as_str())
+        .unwrap_or("Unknown error");
+
+    json!({
+        "type": "error",
+        "error": {"type": error_type, "message": message},
+    })
+}
+
+/// 当前正在输出的内容块
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Thinking,
+    Text,
+    /// OpenAI tool_calls 中的索引
+    Tool(u64),
+}
+
+/// 将 OpenAI `chat.completion.chunk` 流转换为 Anthropic 流式事件
+pub struct StreamTranslator {
+    started: bool,
+    finished: bool,
+    /// 下一个内容块的索引
+    next_index: u64,
+    current: Option<Block>,
+    /// OpenAI tool_calls 索引 -> Anthropic 内容块索引
+    tool_blocks: HashMap<u64, u64>,
+    stop_reason: Value,
+    usage: Value,
+}
+
+impl StreamTranslator {
+    pub fn new() -> Self {
+        Self {
+            started: false,
+            finished: false,
+            next_index: 0,
+            current: None,
+            tool_blocks: HashMap::new(),
+            stop_reason: Value::Null,
+            usage: Value::Null,
+        }
+    }
+
+    fn start_message(&mut self, chunk: &Value, output: &mut Vec<SseEvent>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        output.push(SseEvent::named(
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": chunk["id"],
+                    "type": "message",
+                    "role": "assistant",
+                    "model": chunk["model"],
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": {"input_tokens": 0, "output_tokens": 0},
+                },
+            }),
+        ));
+    }
+
+    fn close_block(&mut self, output: &mut Vec<SseEvent>) {
+        if self.current.take().is_some() {
+            output.push(SseEvent::named(
+                "content_block_stop",
+                json!({"type": "content_block_stop", "index": self.next_index - 1}),
+            ));
+        }
+    }
+
+    /// 切换到指定内容块，必要时关闭上一个块并开始新块
+    fn open_block(&mut self, block: Block, content_block: Value, output: &mut Vec<SseEvent>) {
+        if self.current == Some(block) {
+            return;
+        }
+        self.close_block(output);
+        if let Block::Tool(tool_index) = block {
+            self.tool_blocks.insert(tool_index, self.next_index);
+        }
+        output.push(SseEvent::named(
+            "content_block_start",
+            json!({
+                "type": "content_block_start",
+                "index": self.next_index,
+                "content_block": content_block,
+            }),
+        ));
+        self.current = Some(block);
+        self.next_index += 1;
+    }
+
+    fn delta(&self, delta: Value) -> SseEvent {
+        SseEvent::named(
+            "content_block_delta",
+            json!({
+                "type": "content_block_delta",
+                "index": self.next_index - 1,
+                "delta": delta,
+            }),
+        )
+    }
+
+    /// 输出结束事件，上游的用量块可能在 finish_reason 之后才到达，所以等到流结束时再输出
+    fn finish_message(&mut self) -> Vec<SseEvent> {
+        if !self.started || self.finished {
+            return Vec::new();
+        }
+        self.finished = true;
+
+        let mut output = Vec::new();
+        self.close_block(&mut output);
+        output.push(SseEvent::named(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": self.stop_reason, "stop_sequence": null},
+                "usage": convert_usage(&self.usage),
+            }),
+        ));
+        output.push(SseEvent::named(
+            "message_stop",
+            json!({"type": "message_stop"}),
+        ));
+        output
+    }
+}
+
+impl SseTranslator for StreamTranslator {
+    fn translate(&mut self, _event: &str, data: &str) -> Vec<SseEvent> {
+        if data.trim() == "[DONE]" {
+            return self.finish_message();
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            return Vec::new();
+        };
+
+        let mut output = Vec::new();
+        if !chunk["error"].is_null() {
+            output.push(SseEvent::named("error", convert_error(500, &chunk)));
+            return output;
+        }
+
+        self.start_message(&chunk, &mut output);
+        if !chunk["usage"].is_null() {
+            self.usage = chunk["usage"].clone();
+        }
+
+        let choice = &chunk["choices"][0];
+        let delta = &choice["delta"];
+
+        if let Some(reasoning) = delta["reasoning_content"]
+            .as_str()
+            .filter(|r| !r.is_empty())
+        {
+            self.open_block(
+                Block::Thinking,
+                json!({"type": "thinking", "thinking": ""}),
+                &mut output,
+            );
+            output.push(self.delta(json!({"type": "thinking_delta", "thinking": reasoning})));
+        }
+
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            self.open_block(
+                Block::Text,
+                json!({"type": "text", "text": ""}),
+                &mut output,
+            );
+            output.push(self.delta(json!({"type": "text_delta", "text": text})));
+        }
+
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let tool_index = call["index"].as_u64().unwrap_or(0);
+            if !self.tool_blocks.contains_key(&tool_index) {
+                self.open_block(
+                    Block::Tool(tool_index),
+                    json!({
+                        "type": "tool_use",
+                        "id": call["id"],
+                        "name": call["function"]["name"],
+                        "input": {},
+                    }),
+                    &mut output,
+                );
+            }
+            if let Some(arguments) = call["function"]["arguments"]
+                .as_str()
+                .filter(|a| !a.is_empty())
+            {
+                // 同一个调用的参数片段只会出现在当前块中
+                if self.current == Some(Block::Tool(tool_index)) {
+                    output.push(
+                        self.delta(json!({"type": "input_json_delta", "partial_json": arguments})),
+                    );
+                }
+            }
+        }
+
+        if !choice["finish_reason"].is_null() {
+            self.stop_reason = stop_reason(&choice["finish_reason"]);
+        }
+
+        output
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        self.finish_message()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_splits_tool_results_into_tool_messages() {
+        let payload = json!({
+            "model": "claude",
+            "system": [{"type": "text", "text": "be brief"}],
+            "max_tokens": 100,
+            "stream": true,
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Checking"},
+                    {"type": "tool_use", "id": "tu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "tu_1", "content": "sunny"},
+                    {"type": "text", "text": "thanks"}
+                ]}
+            ],
+            "tool_choice": {"type": "any", "disable_parallel_tool_use": true}
+        });
+
+        let body = convert_request(&payload);
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(
+            messages[0],
+            json!({"role": "system", "content": "be brief"})
+        );
+        assert_eq!(messages[2]["content"], "Checking");
+        assert_eq!(
+            messages[2]["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":\"Paris\"}"
+        );
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "tu_1");
+        assert_eq!(messages[4]["content"][0]["text"], "thanks");
+        // 用量块由转发时按提供者设置注入
+        assert!(body.get("stream_options").is_none());
+        assert_eq!(body["tool_choice"], "required");
+        assert_eq!(body["parallel_tool_calls"], false);
+    }
+
+    #[test]
+    fn response_converts_message_and_usage() {
+        let body = json!({
+            "id": "c1",
+            "model": "gpt-4",
+            "choices": [{
+                "message": {"role": "assistant", "content": "Hi", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "f", "arguments": "{\"a\":1}"}}
+                ]},
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 3, "prompt_tokens_details": {"cached_tokens": 4}}
+        });
+
+        let response = convert_response(&body);
+        assert_eq!(
+            response["content"][0],
+            json!({"type": "text", "text": "Hi"})
+        );
+        assert_eq!(response["content"][1]["input"], json!({"a": 1}));
+        assert_eq!(response["stop_reason"], "tool_use");
+        assert_eq!(
+            response["usage"],
+            json!({"input_tokens": 6, "output_tokens": 3, "cache_read_input_tokens": 4})
+        );
+    }
+
+    #[test]
+    fn stream_translates_chunks_to_events() {
+        let mut translator = StreamTranslator::new();
+        let mut output = Vec::new();
+        for chunk in [
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"delta": {"role": "assistant", "content": "Hi"}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "f", "arguments": "{}"}}]}}]}),
+            json!({"choices": [{"delta": {}, "finish_reason": "tool_calls"}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 5, "completion_tokens": 2}}),
+        ] {
+            output.extend(translator.translate("", &chunk.to_string()));
+        }
+        output.extend(translator.translate("", "[DONE]"));
+        // 上游流结束时不会重复输出结束事件
+        output.extend(translator.finish());
+
+        let events: Vec<&str> = output.iter().filter_map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        let delta: Value = serde_json::from_str(&output[7].data).unwrap();
+        assert_eq!(delta["delta"]["stop_reason"], "tool_use");
+        assert_eq!(delta["usage"]["output_tokens"], 2);
+        let tool_start: Value = serde_json::from_str(&output[4].data).unwrap();
+        assert_eq!(tool_start["index"], 1);
+        assert_eq!(tool_start["content_block"]["id"], "call_1");
+    }
+}
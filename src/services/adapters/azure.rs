@@ -0,0 +1,33 @@
+use crate::services::ai::EndpointType;
+
+/// 默认的 `api-version` 查询参数
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// 构建 Azure OpenAI 请求地址，部署名称取自模型映射
+///
+/// 端点可以是包含 `{deployment}` 占位符的完整地址，也可以只填写资源地址，
+/// 例如 `https://my-resource.openai.azure.com`，此时按端点类型补全路径
+pub fn request_url(
+    url: &str,
+    deployment: &str,
+    endpoint_type: EndpointType,
+    api_version: &str,
+) -> String {
+    let url = if url.contains("{deployment}") {
+        url.replace("{deployment}", deployment)
+    } else {
+        let path = match endpoint_type {
+            EndpointType::Completions | EndpointType::Messages => "chat/completions",
+            EndpointType::Embeddings => "embeddings",
+        };
+        format!(
+            "{}/openai/deployments/{}/{}",
+            url.trim_end_matches('/'),
+            deployment,
+            path
+        )
+    };
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}api-version={}", url, separator, api_version)
+}
@@ -0,0 +1,612 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::{content_text, parse_data_url, ResponseOptions, SseEvent, SseTranslator};
+
+/// Gemini 不支持的 JSON Schema 关键字，转换工具参数时移除
+const UNSUPPORTED_SCHEMA_KEYS: [&str; 4] = ["$schema", "additionalProperties", "strict", "$id"];
+
+/// 构建 `generateContent` / `streamGenerateContent` 请求地址
+pub fn request_url(base_url: &str, model: &str, stream: bool) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    if stream {
+        format!("{}/{}:streamGenerateContent?alt=sse", base_url, model)
+    } else {
+        format!("{}/{}:generateContent", base_url, model)
+    }
+}
+
+/// 将 OpenAI chat completions 请求转换为 Gemini generateContent 请求
+pub fn convert_request(payload: &Value) -> Value {
+    let mut system_parts = Vec::new();
+    let mut contents: Vec<Value> = Vec::new();
+    // tool_call_id -> 函数名，Gemini 的函数结果需要函数名而不是调用 ID
+    let mut call_names: HashMap<String, String> = HashMap::new();
+
+    for message in payload["messages"].as_array().into_iter().flatten() {
+        match message["role"].as_str().unwrap_or("user") {
+            "system" | "developer" => {
+                let text = content_text(&message["content"]);
+                if !text.is_empty() {
+                    system_parts.push(json!({"text": text}));
+                }
+            }
+            "assistant" => {
+                let mut parts = content_parts(&message["content"]);
+                for call in message["tool_calls"].as_array().into_iter().flatten() {
+                    let name = call["function"]["name"].as_str().unwrap_or_default();
+                    if let Some(id) = call["id"].as_str() {
+                        call_names.insert(id.to_string(), name.to_string());
+                    }
+                    let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+                    parts.push(json!({
+                        "functionCall": {
+                            "name": name,
+                            "args": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+                        }
+                    }));
+                }
+                push_content(&mut contents, "model", parts);
+            }
+            "tool" => {
+                let name = message["tool_call_id"]
+                    .as_str()
+                    .and_then(|id| call_names.get(id))
+                    .cloned()
+                    .unwrap_or_default();
+                // 函数结果必须是对象，不是 JSON 对象的内容包装一层
+                let text = content_text(&message["content"]);
+                let response = match serde_json::from_str::<Value>(&text) {
+                    Ok(value @ Value::Object(_)) => value,
+                    _ => json!({"content": text}),
+                };
+                let part = json!({"functionResponse": {"name": name, "response": response}});
+                push_content(&mut contents, "user", vec![part]);
+            }
+            _ => push_content(&mut contents, "user", content_parts(&message["content"])),
+        }
+    }
+
+    let mut body = Map::new();
+    body.insert("contents".to_string(), json!(contents));
+
+    if !system_parts.is_empty() {
+        body.insert(
+            "systemInstruction".to_string(),
+            json!({"parts": system_parts}),
+        );
+    }
+
+    let mut generation_config = Map::new();
+    for (from, to) in [
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+        ("top_k", "topK"),
+        ("n", "candidateCount"),
+        ("presence_penalty", "presencePenalty"),
+        ("frequency_penalty", "frequencyPenalty"),
+        ("seed", "seed"),
+    ] {
+        if !payload[from].is_null() {
+            generation_config.insert(to.to_string(), payload[from].clone());
+        }
+    }
+    if let Some(max_tokens) = payload["max_tokens"]
+        .as_u64()
+        .or_else(|| payload["max_completion_tokens"].as_u64())
+    {
+        generation_config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+    }
+    match &payload["stop"] {
+        Value::String(stop) => {
+            generation_config.insert("stopSequences".to_string(), json!([stop]));
+        }
+        Value::Array(stops) => {
+            generation_config.insert("stopSequences".to_string(), json!(stops));
+        }
+        _ => {}
+    }
+    match payload["response_format"]["type"].as_str() {
+        Some("json_object") => {
+            generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+        }
+        Some("json_schema") => {
+            generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+            let schema = &payload["response_format"]["json_schema"]["schema"];
+            if !schema.is_null() {
+                generation_config.insert("responseSchema".to_string(), clean_schema(schema));
+            }
+        }
+        _ => {}
+    }
+    if !generation_config.is_empty() {
+        body.insert(
+            "generationConfig".to_string(),
+            Value::Object(generation_config),
+        );
+    }
+
+    if let Some(tools) = payload["tools"].as_array() {
+        let declarations: Vec<Value> = tools
+            .iter()
+            .filter(|t| t["type"] == "function")
+            .map(|t| {
+                let function = &t["function"];
+                let mut declaration = json!({"name": function["name"]});
+                if let Some(description) = function["description"].as_str() {
+                    declaration["description"] = json!(description);
+                }
+                if !function["parameters"].is_null() {
+                    declaration["parameters"] = clean_schema(&function["parameters"]);
+                }
+                declaration
+            })
+            .collect();
+        if !declarations.is_empty() {
+            body.insert(
+                "tools".to_string(),
+                json!([{"functionDeclarations": declarations}]),
+            );
+        }
+    }
+
+    let function_calling_config = match &payload["tool_choice"] {
+        Value::String(choice) => match choice.as_str() {
+            "auto" => Some(json!({"mode": "AUTO"})),
+            "required" => Some(json!({"mode": "ANY"})),
+            "none" => Some(json!({"mode": "NONE"})),
+            _ => None,
+        },
+        Value::Object(choice) => choice
+            .get("function")
+            .and_then(|f| f["name"].as_str())
+            .map(|name| json!({"mode": "ANY", "allowedFunctionNames": [name]})),
+        _ => None,
+    };
+    if let Some(config) = function_calling_config {
+        body.insert(
+            "toolConfig".to_string(),
+            json!({"functionCallingConfig": config}),
+        );
+    }
+
+    Value::Object(body)
+}
+
+/// 追加内容，相同角色的连续消息合并为一条
+fn push_content(contents: &mut Vec<Value>, role: &str, parts: Vec<Value>) {
+    if parts.is_empty() {
+        return;
+    }
+
+    if let Some(last) = contents.last_mut() {
+        if last["role"] == role {
+            if let Some(existing) = last["parts"].as_array_mut() {
+                existing.extend(parts);
+                return;
+            }
+        }
+    }
+
+    contents.push(json!({"role": role, "parts": parts}));
+}
+
+/// 将 OpenAI 消息内容转换为 Gemini parts
+fn content_parts(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) if !text.is_empty() => vec![json!({"text": text})],
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part["type"].as_str() {
+                Some("text") => part["text"]
+                    .as_str()
+                    .filter(|t| !t.is_empty())
+                    .map(|text| json!({"text": text})),
+                Some("image_url") => {
+                    part["image_url"]["url"]
+                        .as_str()
+                        .map(|url| match parse_data_url(url) {
+                            Some((mime_type, data)) => {
+                                json!({"inlineData": {"mimeType": mime_type, "data": data}})
+                            }
+                            None => json!({"fileData": {"fileUri": url}}),
+                        })
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 递归移除 Gemini 不支持的 JSON Schema 关键字
+fn clean_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(key, _)| !UNSUPPORTED_SCHEMA_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), clean_schema(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(clean_schema).collect()),
+        other => other.clone(),
+    }
+}
+
+/// 转换停止原因，有函数调用时视为 tool_calls
+fn finish_reason(reason: &Value, has_tool_calls: bool) -> Value {
+    match reason.as_str() {
+        None => Value::Null,
+        Some(_) if has_tool_calls => json!("tool_calls"),
+        Some("STOP") => json!("stop"),
+        Some("MAX_TOKENS") => json!("length"),
+        Some("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII") => {
+            json!("content_filter")
+        }
+        Some(_) => json!("stop"),
+    }
+}
+
+/// 转换用量统计，思考 token 计入 completion_tokens
+fn convert_usage(usage: &Value) -> Value {
+    let prompt = usage["promptTokenCount"].as_u64().unwrap_or(0);
+    let completion = usage["candidatesTokenCount"].as_u64().unwrap_or(0)
+        + usage["thoughtsTokenCount"].as_u64().unwrap_or(0);
+    let total = usage["totalTokenCount"]
+        .as_u64()
+        .unwrap_or(prompt + completion);
+
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": total,
+        "prompt_tokens_details": {
+            "cached_tokens": usage["cachedContentTokenCount"].as_u64().unwrap_or(0),
+        },
+    })
+}
+
+/// 候选结果中的文本、思考内容和函数调用
+struct CandidateParts {
+    text: String,
+    reasoning: String,
+    tool_calls: Vec<Value>,
+}
+
+/// 拆分候选结果的 parts，`call_offset` 用于生成不重复的调用 ID
+fn split_parts(candidate: &Value, call_offset: usize) -> CandidateParts {
+    let mut parts = CandidateParts {
+        text: String::new(),
+        reasoning: String::new(),
+        tool_calls: Vec::new(),
+    };
+
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(text) = part["text"].as_str() {
+            if part["thought"] == true {
+                parts.reasoning.push_str(text);
+            } else {
+                parts.text.push_str(text);
+            }
+        } else if !part["functionCall"].is_null() {
+            let call = &part["functionCall"];
+            let index = call_offset + parts.tool_calls.len();
+            parts.tool_calls.push(json!({
+                "index": index,
+                "id": format!("call_{}", index),
+                "type": "function",
+                "function": {
+                    "name": call["name"],
+                    "arguments": call["args"].to_string(),
+                },
+            }));
+        }
+    }
+
+    parts
+}
+
+/// 生成响应 ID，优先使用上游返回的 responseId
+fn response_id(body: &Value) -> String {
+    body["responseId"]
+        .as_str()
+        .map(|id| format!("chatcmpl-{}", id))
+        .unwrap_or_else(|| format!("chatcmpl-{}", chrono::Utc::now().timestamp_millis()))
+}
+
+/// 将 Gemini generateContent 响应转换为 OpenAI chat completion 响应
+pub fn convert_response(body: &Value) -> Value {
+    let choices: Vec<Value> = body["candidates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let parts = split_parts(candidate, 0);
+            let has_tool_calls = !parts.tool_calls.is_empty();
+
+            let mut message = json!({
+                "role": "assistant",
+                "content": if parts.text.is_empty() && has_tool_calls { Value::Null } else { json!(parts.text) },
+            });
+            if !parts.reasoning.is_empty() {
+                message["reasoning_content"] = json!(parts.reasoning);
+            }
+            if has_tool_calls {
+                let tool_calls: Vec<Value> = parts
+                    .tool_calls
+                    .into_iter()
+                    .map(|mut call| {
+                        if let Some(call) = call.as_object_mut() {
+                            call.remove("index");
+                        }
+                        call
+                    })
+                    .collect();
+                message["tool_calls"] = json!(tool_calls);
+            }
+
+            json!({
+                "index": candidate["index"].as_u64().unwrap_or(index as u64),
+                "message": message,
+                "finish_reason": finish_reason(&candidate["finishReason"], has_tool_calls),
+            })
+        })
+        .collect();
+
+    json!({
+        "id": response_id(body),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": body["modelVersion"],
+        "choices": choices,
+        "usage": convert_usage(&body["usageMetadata"]),
+    })
+}
+
+/// 将 Gemini 流式响应块转换为 OpenAI `chat.completion.chunk`
+///
+/// Gemini 的每个 SSE 事件都是一个完整的 generateContent 响应片段，且没有结束事件，
+/// 用量块和 `[DONE]` 在上游流结束时补发
+pub struct StreamTranslator {
+    options: ResponseOptions,
+    id: Option<String>,
+    model: Value,
+    created: i64,
+    /// 每个候选结果已经输出的函数调用数量
+    tool_calls: HashMap<u64, usize>,
+    /// 已经输出过角色的候选结果
+    started: Vec<u64>,
+    usage: Value,
+}
+
+impl StreamTranslator {
+    pub fn new(options: ResponseOptions) -> Self {
+        Self {
+            options,
+            id: None,
+            model: Value::Null,
+            created: chrono::Utc::now().timestamp(),
+            tool_calls: HashMap::new(),
+            started: Vec::new(),
+            usage: Value::Null,
+        }
+    }
+
+    fn chunk(&self, choices: Vec<Value>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        })
+    }
+}
+
+impl SseTranslator for StreamTranslator {
+    fn translate(&mut self, _event: &str, data: &str) -> Vec<SseEvent> {
+        let Ok(body) = serde_json::from_str::<Value>(data) else {
+            return Vec::new();
+        };
+
+        if !body["error"].is_null() {
+            return vec![SseEvent::data(json!({"error": body["error"]}))];
+        }
+
+        if self.id.is_none() {
+            self.id = Some(response_id(&body));
+        }
+        if !body["modelVersion"].is_null() {
+            self.model = body["modelVersion"].clone();
+        }
+        if !body["usageMetadata"].is_null() {
+            self.usage = body["usageMetadata"].clone();
+        }
+
+        let mut choices = Vec::new();
+        for (position, candidate) in body["candidates"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let index = candidate["index"].as_u64().unwrap_or(position as u64);
+            let call_offset = self.tool_calls.get(&index).copied().unwrap_or(0);
+            let parts = split_parts(candidate, call_offset);
+
+            let mut delta = Map::new();
+            if !self.started.contains(&index) {
+                self.started.push(index);
+                delta.insert("role".to_string(), json!("assistant"));
+            }
+            if !parts.text.is_empty() {
+                delta.insert("content".to_string(), json!(parts.text));
+            }
+            if !parts.reasoning.is_empty() {
+                delta.insert("reasoning_content".to_string(), json!(parts.reasoning));
+            }
+            if !parts.tool_calls.is_empty() {
+                self.tool_calls
+                    .insert(index, call_offset + parts.tool_calls.len());
+                delta.insert("tool_calls".to_string(), json!(parts.tool_calls));
+            }
+
+            let has_tool_calls = self.tool_calls.get(&index).copied().unwrap_or(0) > 0;
+            let finish = finish_reason(&candidate["finishReason"], has_tool_calls);
+            if delta.is_empty() && finish.is_null() {
+                continue;
+            }
+
+            choices.push(json!({
+                "index": index,
+                "delta": delta,
+                "finish_reason": finish,
+            }));
+        }
+
+        if choices.is_empty() {
+            return Vec::new();
+        }
+        vec![SseEvent::data(self.chunk(choices))]
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut output = Vec::new();
+        if self.options.include_usage && !self.usage.is_null() {
+            let mut chunk = self.chunk(Vec::new());
+            chunk["usage"] = convert_usage(&self.usage);
+            output.push(SseEvent::data(chunk));
+        }
+        output.push(SseEvent::data("[DONE]"));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_url_selects_stream_method() {
+        assert_eq!(
+            request_url("https://example.com/v1beta/models/", "gemini-pro", false),
+            "https://example.com/v1beta/models/gemini-pro:generateContent"
+        );
+        assert_eq!(
+            request_url("https://example.com/v1beta/models", "gemini-pro", true),
+            "https://example.com/v1beta/models/gemini-pro:streamGenerateContent?alt=sse"
+        );
+    }
+
+    #[test]
+    fn request_converts_messages_tools_and_config() {
+        let payload = json!({
+            "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "sunny"}
+            ],
+            "max_tokens": 100,
+            "top_p": 0.5,
+            "tools": [{"type": "function", "function": {
+                "name": "get_weather",
+                "parameters": {"type": "object", "additionalProperties": false, "properties": {}}
+            }}],
+            "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+        });
+
+        let body = convert_request(&payload);
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"]["args"],
+            json!({"city": "Paris"})
+        );
+        // 函数结果使用函数名，非 JSON 对象的内容包装一层
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"],
+            json!({"name": "get_weather", "response": {"content": "sunny"}})
+        );
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 100);
+        assert_eq!(body["generationConfig"]["topP"], 0.5);
+        let declaration = &body["tools"][0]["functionDeclarations"][0];
+        assert!(declaration["parameters"]["additionalProperties"].is_null());
+        assert_eq!(
+            body["toolConfig"]["functionCallingConfig"],
+            json!({"mode": "ANY", "allowedFunctionNames": ["get_weather"]})
+        );
+    }
+
+    #[test]
+    fn response_converts_candidates_and_usage() {
+        let body = json!({
+            "responseId": "abc",
+            "modelVersion": "gemini-x",
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "thinking", "thought": true},
+                    {"text": "Hello"},
+                    {"functionCall": {"name": "f", "args": {"x": 1}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 4, "thoughtsTokenCount": 2, "totalTokenCount": 9}
+        });
+
+        let response = convert_response(&body);
+        assert_eq!(response["id"], "chatcmpl-abc");
+        assert_eq!(response["model"], "gemini-x");
+        let choice = &response["choices"][0];
+        assert_eq!(choice["message"]["content"], "Hello");
+        assert_eq!(choice["message"]["reasoning_content"], "thinking");
+        assert_eq!(choice["message"]["tool_calls"][0]["id"], "call_0");
+        assert!(choice["message"]["tool_calls"][0]["index"].is_null());
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["completion_tokens"], 6);
+        assert_eq!(response["usage"]["total_tokens"], 9);
+    }
+
+    #[test]
+    fn stream_emits_usage_and_done_on_finish() {
+        let mut translator = StreamTranslator::new(ResponseOptions {
+            stream: true,
+            include_usage: true,
+        });
+        let first = translator.translate(
+            "",
+            &json!({"candidates": [{"content": {"parts": [{"text": "Hel"}]}}]}).to_string(),
+        );
+        let second = translator.translate(
+            "",
+            &json!({
+                "candidates": [{"content": {"parts": [{"text": "lo"}]}, "finishReason": "MAX_TOKENS"}],
+                "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 4, "totalTokenCount": 7}
+            })
+            .to_string(),
+        );
+        let finish = translator.finish();
+
+        let first: Value = serde_json::from_str(&first[0].data).unwrap();
+        let second: Value = serde_json::from_str(&second[0].data).unwrap();
+        assert_eq!(first["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(first["choices"][0]["delta"]["content"], "Hel");
+        assert!(second["choices"][0]["delta"]["role"].is_null());
+        assert_eq!(second["choices"][0]["finish_reason"], "length");
+        assert_eq!(first["id"], second["id"]);
+
+        assert_eq!(finish.len(), 2);
+        let usage: Value = serde_json::from_str(&finish[0].data).unwrap();
+        assert_eq!(usage["usage"]["total_tokens"], 7);
+        assert_eq!(finish[1].data, "[DONE]");
+    }
+}
@@ -0,0 +1,597 @@
+use bytes::Bytes;
+use eventsource_stream::Eventsource;
+use futures::stream::{self, BoxStream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{AuthScheme, Provider, ProviderKind};
+use crate::error::{AppError, AppResult};
+use crate::services::ai::EndpointType;
+use crate::services::key_health;
+
+pub mod anthropic;
+pub mod anthropic_inbound;
+pub mod azure;
+pub mod gemini;
+
+/// 发给客户端的响应字节流
+pub type ByteStream = BoxStream<'static, Result<Bytes, io::Error>>;
+
+/// 转换响应时需要的原始请求信息
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseOptions {
+    /// 是否为流式请求
+    pub stream: bool,
+    /// 客户端是否通过 `stream_options.include_usage` 请求了用量块
+    pub include_usage: bool,
+}
+
+impl ResponseOptions {
+    pub fn from_payload(payload: &Value) -> Self {
+        Self {
+            stream: payload["stream"].as_bool().unwrap_or(false),
+            include_usage: payload["stream_options"]["include_usage"]
+                .as_bool()
+                .unwrap_or(false),
+        }
+    }
+
+    /// 转发给该提供者时使用的选项，流式请求尽量要求上游返回用量块以便统计 token 用量
+    ///
+    /// Anthropic 和 Gemini 的用量块由转换器生成；OpenAI 兼容的上游需要在请求中注入
+    /// `stream_options.include_usage`，可以通过 `stream_usage` 按提供者关闭
+    pub fn for_provider(self, provider: &Provider) -> Self {
+        let upstream_usage = match provider.kind {
+            ProviderKind::Anthropic | ProviderKind::Gemini => true,
+            ProviderKind::OpenAI | ProviderKind::Azure => provider.stream_usage.unwrap_or(true),
+        };
+        Self {
+            include_usage: self.include_usage || (self.stream && upstream_usage),
+            ..self
+        }
+    }
+}
+
+/// 转换后输出的一个 SSE 事件
+pub struct SseEvent {
+    /// 事件名称，OpenAI 格式的流不使用事件名称
+    pub event: Option<&'static str>,
+    pub data: String,
+}
+
+impl SseEvent {
+    pub fn data(data: impl ToString) -> Self {
+        Self {
+            event: None,
+            data: data.to_string(),
+        }
+    }
+
+    pub fn named(event: &'static str, data: impl ToString) -> Self {
+        Self {
+            event: Some(event),
+            data: data.to_string(),
+        }
+    }
+}
+
+/// 将 SSE 事件逐个转换为另一种协议的事件
+pub trait SseTranslator {
+    fn translate(&mut self, event: &str, data: &str) -> Vec<SseEvent>;
+
+    /// 上游流结束时调用，用于补发没有对应上游事件的结束块
+    fn finish(&mut self) -> Vec<SseEvent> {
+        Vec::new()
+    }
+}
+
+/// 提取 OpenAI 消息内容中的纯文本
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 解析 `data:image/png;base64,xxx` 形式的 data URL，返回媒体类型和 base64 数据
+fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+    Some((meta.trim_end_matches(";base64"), data))
+}
+
+/// 该类型的提供者是否需要转换响应
+pub fn translates_response(kind: ProviderKind, endpoint_type: EndpointType) -> bool {
+    match endpoint_type {
+        EndpointType::Messages => kind != ProviderKind::Anthropic,
+        EndpointType::Completions | EndpointType::Embeddings => {
+            matches!(kind, ProviderKind::Anthropic | ProviderKind::Gemini)
+        }
+    }
+}
+
+/// 将 OpenAI 格式的请求体转换为上游格式，并替换为上游真实模型名称
+///
+/// 客户端使用 OpenAI 格式，`EndpointType::Messages` 的请求使用 Anthropic 格式，收到上游响应后
+/// 再通过 `convert_response` 转换回来；`options` 为 `ResponseOptions::for_provider` 得到的选项
+pub fn convert_request(
+    provider: &Provider,
+    model: &str,
+    payload: &Value,
+    endpoint_type: EndpointType,
+    options: ResponseOptions,
+) -> AppResult<Value> {
+    if let EndpointType::Messages = endpoint_type {
+        // Anthropic 提供者原样接收请求，只替换模型名称，其他提供者经 OpenAI 格式转换
+        if provider.kind == ProviderKind::Anthropic {
+            let mut body = payload.clone();
+            body["model"] = Value::String(model.to_string());
+            return Ok(body);
+        }
+        let payload = anthropic_inbound::convert_request(payload);
+        return convert_request(
+            provider,
+            model,
+            &payload,
+            EndpointType::Completions,
+            options,
+        );
+    }
+
+    match provider.kind {
+        // Azure 按部署名称路由，请求体与 OpenAI 相同
+        ProviderKind::OpenAI | ProviderKind::Azure => {
+            // 只替换payload中的model字段
+            let mut body = payload.clone();
+            body["model"] = Value::String(model.to_string());
+            // 要求上游在流式响应的最后返回用量块，客户端传入的 stream_options 已经校验为对象
+            if options.stream && options.include_usage {
+                match body.get_mut("stream_options") {
+                    Some(Value::Object(stream_options)) => {
+                        stream_options.insert("include_usage".to_string(), Value::Bool(true));
+                    }
+                    _ => body["stream_options"] = json!({"include_usage": true}),
+                }
+            }
+            Ok(body)
+        }
+        ProviderKind::Anthropic | ProviderKind::Gemini
+            if matches!(endpoint_type, EndpointType::Embeddings) =>
+        {
+            Err(AppError::EndpointNotSupported(format!(
+                "Provider '{}' does not support embeddings endpoint",
+                provider.name
+            )))
+        }
+        ProviderKind::Anthropic => Ok(anthropic::convert_request(model, payload)),
+        // Gemini 的模型名称在请求地址中
+        ProviderKind::Gemini => Ok(gemini::convert_request(payload)),
+    }
+}
+
+/// 根据提供者类型生成最终的请求地址
+pub fn request_url(
+    provider: &Provider,
+    url: &str,
+    model: &str,
+    endpoint_type: EndpointType,
+    options: ResponseOptions,
+) -> String {
+    match provider.kind {
+        ProviderKind::OpenAI | ProviderKind::Anthropic => url.to_string(),
+        ProviderKind::Gemini => gemini::request_url(url, model, options.stream),
+        ProviderKind::Azure => azure::request_url(
+            url,
+            model,
+            endpoint_type,
+            provider
+                .api_version
+                .as_deref()
+                .unwrap_or(azure::DEFAULT_API_VERSION),
+        ),
+    }
+}
+
+/// 各提供者类型默认的认证方式
+fn default_auth(kind: ProviderKind) -> AuthScheme {
+    match kind {
+        ProviderKind::OpenAI => AuthScheme::Bearer,
+        ProviderKind::Anthropic => AuthScheme::Header {
+            name: "x-api-key".to_string(),
+            prefix: None,
+        },
+        ProviderKind::Gemini => AuthScheme::Query {
+            name: "key".to_string(),
+        },
+        ProviderKind::Azure => AuthScheme::Header {
+            name: "api-key".to_string(),
+            prefix: None,
+        },
+    }
+}
+
+/// 替换请求头和查询参数模板中的 `{key}`、`{model}`、`{provider}` 占位符
+fn render_template(template: &str, provider: &Provider, model: &str, api_key: &str) -> String {
+    template
+        .replace("{key}", api_key)
+        .replace("{model}", model)
+        .replace("{provider}", &provider.name)
+}
+
+/// 构建发往上游的请求，每次换用密钥重试都会重新构建
+///
+/// `forwarded` 为透传的客户端请求头，会被认证和自定义请求头覆盖
+pub fn build_request(
+    client: &reqwest::Client,
+    provider: &Provider,
+    url: &str,
+    model: &str,
+    api_key: &str,
+    body: &Value,
+    forwarded: HeaderMap,
+) -> reqwest::RequestBuilder {
+    let mut headers = forwarded;
+    let mut query: Vec<(String, String)> = Vec::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    // 认证方式，未配置时使用提供者类型的默认方式
+    let auth = provider
+        .auth
+        .clone()
+        .unwrap_or_else(|| default_auth(provider.kind));
+    match auth {
+        AuthScheme::Bearer => {
+            if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+                headers.insert(AUTHORIZATION, value);
+            }
+        }
+        AuthScheme::Header { name, prefix } => {
+            let value = format!("{}{}", prefix.unwrap_or_default(), api_key);
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        AuthScheme::Query { name } => query.push((name, api_key.to_string())),
+        AuthScheme::None => {}
+    }
+
+    if provider.kind == ProviderKind::Anthropic {
+        let version = provider
+            .anthropic_version
+            .as_deref()
+            .unwrap_or(anthropic::DEFAULT_VERSION);
+        if let Ok(value) = HeaderValue::from_str(version) {
+            headers.insert("anthropic-version", value);
+        }
+    }
+
+    // 自定义请求头覆盖默认请求头，配置校验时已经保证名称合法
+    for (name, template) in &provider.headers {
+        let value = render_template(template, provider, model, api_key);
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+
+    for (name, template) in &provider.query {
+        query.push((
+            name.clone(),
+            render_template(template, provider, model, api_key),
+        ));
+    }
+
+    let mut builder = client.post(url).headers(headers).json(body);
+    if !query.is_empty() {
+        builder = builder.query(&query);
+    }
+    builder
+}
+
+/// 上游错误信息不是 JSON 时最多保留的字符数
+const MAX_ERROR_TEXT_CHARS: usize = 1000;
+
+/// 将上游的错误响应统一转换为 OpenAI 错误格式，保留上游状态码
+///
+/// 支持 OpenAI/Azure 的 `{"error": {message, type, code, param}}`、Anthropic 的
+/// `{"type": "error", "error": {type, message}}` 和 Gemini 的 `{"error": {code, message, status}}`，
+/// 上游限流（429）时保留 `Retry-After`，供客户端决定等待时间
+pub fn convert_error(
+    kind: ProviderKind,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &str,
+) -> AppError {
+    let value: Value = serde_json::from_str(body).unwrap_or_default();
+    // Gemini 部分接口以数组形式返回错误
+    let value = match value {
+        Value::Array(mut items) if !items.is_empty() => items.swap_remove(0),
+        value => value,
+    };
+    let error = &value["error"];
+
+    let message = error["message"]
+        .as_str()
+        .or_else(|| error.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            let text = body.trim();
+            if text.is_empty() {
+                status
+                    .canonical_reason()
+                    .unwrap_or("Unknown error")
+                    .to_string()
+            } else {
+                text.chars().take(MAX_ERROR_TEXT_CHARS).collect()
+            }
+        });
+
+    let (error_type, code, param) = match kind {
+        ProviderKind::OpenAI | ProviderKind::Azure => (
+            error["type"].as_str().map(str::to_string),
+            error.get("code").filter(|v| !v.is_null()).cloned(),
+            error.get("param").filter(|v| !v.is_null()).cloned(),
+        ),
+        // Anthropic 的错误类型与 OpenAI 基本一致
+        ProviderKind::Anthropic => (error["type"].as_str().map(str::to_string), None, None),
+        // Gemini 的 status 形如 INVALID_ARGUMENT，作为错误码返回
+        ProviderKind::Gemini => (
+            None,
+            error["status"]
+                .as_str()
+                .map(|status| Value::String(status.to_lowercase())),
+            None,
+        ),
+    };
+
+    let retry_after = (status == StatusCode::TOO_MANY_REQUESTS)
+        .then(|| key_health::retry_after(headers))
+        .flatten()
+        .map(|wait| wait.as_secs_f64().ceil().max(1.0) as u32);
+
+    AppError::Upstream {
+        status,
+        message,
+        error_type,
+        code,
+        param,
+        retry_after,
+    }
+}
+
+/// 将上游的成功响应体转换为客户端使用的格式
+///
+/// `EndpointType::Messages` 的请求由 Anthropic 提供者处理时原样返回，
+/// 其他提供者的响应先转换为 OpenAI 格式，再转换为 Anthropic 格式
+pub async fn convert_response(
+    kind: ProviderKind,
+    endpoint_type: EndpointType,
+    body: ByteStream,
+    options: ResponseOptions,
+) -> AppResult<ByteStream> {
+    if !matches!(endpoint_type, EndpointType::Messages) {
+        return openai_response(kind, body, options).await;
+    }
+    if kind == ProviderKind::Anthropic {
+        return Ok(body);
+    }
+    let body = openai_response(kind, body, options).await?;
+    if options.stream {
+        Ok(translate_sse(
+            body,
+            anthropic_inbound::StreamTranslator::new(),
+        ))
+    } else {
+        translate_json(body, anthropic_inbound::convert_response).await
+    }
+}
+
+/// 将上游的成功响应体转换为 OpenAI 格式的字节流
+async fn openai_response(
+    kind: ProviderKind,
+    body: ByteStream,
+    options: ResponseOptions,
+) -> AppResult<ByteStream> {
+    match (kind, options.stream) {
+        (ProviderKind::OpenAI | ProviderKind::Azure, _) => Ok(body),
+        (ProviderKind::Anthropic, true) => Ok(translate_sse(
+            body,
+            anthropic::StreamTranslator::new(options),
+        )),
+        (ProviderKind::Anthropic, false) => translate_json(body, anthropic::convert_response).await,
+        (ProviderKind::Gemini, true) => {
+            Ok(translate_sse(body, gemini::StreamTranslator::new(options)))
+        }
+        (ProviderKind::Gemini, false) => translate_json(body, gemini::convert_response).await,
+    }
+}
+
+/// 读取完整的 JSON 响应并转换
+async fn translate_json(
+    mut body: ByteStream,
+    convert: fn(&Value) -> Value,
+) -> AppResult<ByteStream> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut => {
+                AppError::UpstreamTimeout("Upstream response timed out".to_string())
+            }
+            _ => AppError::UpstreamUnavailable(format!("Failed to read upstream response: {}", e)),
+        })?;
+        buffer.extend_from_slice(&chunk);
+    }
+
+    let body: Value = serde_json::from_slice(&buffer)
+        .map_err(|e| AppError::UpstreamUnavailable(format!("Invalid upstream response: {}", e)))?;
+    let bytes = Bytes::from(serde_json::to_vec(&convert(&body))?);
+    Ok(stream::once(async move { Ok(bytes) }).boxed())
+}
+
+/// 解析 SSE 流并逐个事件转换，每个输入事件对应一个输出块
+pub fn translate_sse<S, E, T>(upstream: S, translator: T) -> ByteStream
+where
+    S: futures::Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+    T: SseTranslator + Send + 'static,
+{
+    // 流结束时还需要调用 finish，转换器在两个阶段之间共享
+    let translator = Arc::new(Mutex::new(translator));
+    let finisher = translator.clone();
+
+    let events = upstream.eventsource().map(move |event| match event {
+        Ok(event) => {
+            let output = translator
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .translate(&event.event, &event.data);
+            Ok(encode_sse(output))
+        }
+        Err(e) => Err(io::Error::other(e.to_string())),
+    });
+
+    let tail = stream::once(async move {
+        let output = finisher.lock().unwrap_or_else(|e| e.into_inner()).finish();
+        Ok(encode_sse(output))
+    });
+
+    events
+        .chain(tail)
+        .filter(|chunk| {
+            let keep = !matches!(chunk, Ok(bytes) if bytes.is_empty());
+            async move { keep }
+        })
+        .boxed()
+}
+
+/// 将多个事件编码为一个 SSE 输出块
+fn encode_sse(events: Vec<SseEvent>) -> Bytes {
+    let output: String = events
+        .into_iter()
+        .map(|e| match e.event {
+            Some(event) => format!("event: {}\ndata: {}\n\n", event, e.data),
+            None => format!("data: {}\n\n", e.data),
+        })
+        .collect();
+    Bytes::from(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    fn upstream(error: AppError) -> (StatusCode, String, Option<String>, Option<Value>) {
+        match error {
+            AppError::Upstream {
+                status,
+                message,
+                error_type,
+                code,
+                ..
+            } => (status, message, error_type, code),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn convert_error_keeps_openai_fields() {
+        let body = r#"{"error": {"message": "too long", "type": "invalid_request_error", "code": "context_length_exceeded", "param": "messages"}}"#;
+        let (status, message, error_type, code) = upstream(convert_error(
+            ProviderKind::OpenAI,
+            StatusCode::BAD_REQUEST,
+            &HeaderMap::new(),
+            body,
+        ));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message, "too long");
+        assert_eq!(error_type.as_deref(), Some("invalid_request_error"));
+        assert_eq!(code, Some(json!("context_length_exceeded")));
+    }
+
+    #[test]
+    fn convert_error_reads_gemini_error_array() {
+        let body =
+            r#"[{"error": {"code": 429, "message": "quota", "status": "RESOURCE_EXHAUSTED"}}]"#;
+        let (status, message, error_type, code) = upstream(convert_error(
+            ProviderKind::Gemini,
+            StatusCode::TOO_MANY_REQUESTS,
+            &HeaderMap::new(),
+            body,
+        ));
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(message, "quota");
+        assert_eq!(error_type, None);
+        assert_eq!(code, Some(json!("resource_exhausted")));
+    }
+
+    #[test]
+    fn convert_error_falls_back_to_text_or_reason() {
+        let (_, message, _, _) = upstream(convert_error(
+            ProviderKind::Anthropic,
+            StatusCode::BAD_GATEWAY,
+            &HeaderMap::new(),
+            "<html>bad gateway</html>",
+        ));
+        assert_eq!(message, "<html>bad gateway</html>");
+
+        let (_, message, _, _) = upstream(convert_error(
+            ProviderKind::OpenAI,
+            StatusCode::SERVICE_UNAVAILABLE,
+            &HeaderMap::new(),
+            "",
+        ));
+        assert_eq!(message, "Service Unavailable");
+    }
+
+    #[test]
+    fn convert_error_keeps_retry_after_on_rate_limit() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("30"));
+        let body = r#"{"error": {"message": "slow down", "type": "rate_limit_error"}}"#;
+
+        let error = convert_error(
+            ProviderKind::OpenAI,
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            body,
+        );
+        assert!(matches!(
+            error,
+            AppError::Upstream {
+                retry_after: Some(30),
+                ..
+            }
+        ));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+
+        // 其他状态码不转发 Retry-After
+        let error = convert_error(
+            ProviderKind::OpenAI,
+            StatusCode::SERVICE_UNAVAILABLE,
+            &headers,
+            body,
+        );
+        assert!(matches!(
+            error,
+            AppError::Upstream {
+                retry_after: None,
+                ..
+            }
+        ));
+    }
+}
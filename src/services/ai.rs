@@ -1,15 +1,96 @@
-use axum::{body::Body, http::HeaderMap, response::Response};
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
 use serde_json::{json, Value};
-use tracing::{debug, error};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
 
 use crate::config::Provider;
 use crate::error::{AppError, AppResult};
-use crate::state::AppState;
+use crate::middleware::{extract_token, ClientIdentity};
+use crate::services::adapters::{self, ResponseOptions};
+use crate::services::audit::AuditRecord;
+use crate::services::balancer::InFlightGuard;
+use crate::services::capture::Capture;
+use crate::services::headers;
+use crate::services::key_health::{is_invalid_key_error, mask_key, KeyHealth};
+use crate::services::key_selector::SelectionContext;
+use crate::services::persistence;
+use crate::services::rate_limit::RateLimitScope;
+use crate::services::timeouts::{self, TimeoutKind};
+use crate::services::usage::{self, UsageLabels};
+use crate::state::{AppState, RouteTarget};
 
 #[derive(Debug, Clone, Copy)]
 pub enum EndpointType {
     Completions,
     Embeddings,
+    /// Anthropic Messages 协议，请求和响应都使用 Anthropic 格式，上游地址使用 `completions` 端点
+    Messages,
+}
+
+impl EndpointType {
+    fn name(&self) -> &'static str {
+        match self {
+            EndpointType::Completions => "completions",
+            EndpointType::Embeddings => "embeddings",
+            EndpointType::Messages => "messages",
+        }
+    }
+}
+
+/// 一次转发请求的审计记录和内容捕获，请求结束后写入
+struct RequestTrace {
+    audit: AuditRecord,
+    capture: Option<Capture>,
+    /// 指标中的模型别名，未配置的模型统一为 `unknown`，避免客户端随意构造标签值
+    metric_alias: String,
+}
+
+/// 单个转发目标失败时的错误，`retryable` 表示是否可以回退到下一个目标
+struct TargetError {
+    error: AppError,
+    retryable: bool,
+}
+
+impl TargetError {
+    fn retryable(error: AppError) -> Self {
+        Self {
+            error,
+            retryable: true,
+        }
+    }
+
+    fn fatal(error: AppError) -> Self {
+        Self {
+            error,
+            retryable: false,
+        }
+    }
+}
+
+/// 转发目标返回的成功响应
+struct UpstreamResponse {
+    response: reqwest::Response,
+    /// 在途计数守卫，持续到响应体传输结束
+    in_flight: InFlightGuard,
+    /// 本次请求使用的上游密钥
+    api_key: String,
+}
+
+/// 判断上游状态码是否应该换用其他密钥重试
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 401 | 403 | 429) || status.is_server_error()
+}
+
+/// 判断请求错误是否属于连接类错误
+fn is_retryable_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
 }
 
 pub struct AIService {
@@ -21,28 +102,46 @@ impl AIService {
         Self { state }
     }
 
-    async fn select_api_key(&self, provider: &Provider) -> AppResult<String> {
+    async fn select_api_key(
+        &self,
+        provider: &Provider,
+        excluded: &[String],
+        client_token: Option<&str>,
+    ) -> AppResult<String> {
         if provider.keys.is_empty() {
-            return Err(AppError::Validation(format!(
+            return Err(AppError::UpstreamUnavailable(format!(
                 "No API keys configured for provider '{}'",
                 provider.name
             )));
         }
 
-        // 简单的轮询策略，可以后续改进为更智能的负载均衡
         let key_usage = self.state.key_usage.read().await;
-        let mut min_usage = u64::MAX;
-        let mut selected_key = &provider.keys[0];
-
-        for key in &provider.keys {
-            let usage = key_usage.get(key).map(|v| *v).unwrap_or(0);
-            if usage < min_usage {
-                min_usage = usage;
-                selected_key = key;
-            }
-        }
 
-        Ok(selected_key.clone())
+        // 跳过本次请求中已经失败过的密钥以及处于冷却或禁用状态的密钥
+        let ctx = SelectionContext {
+            provider,
+            candidates: provider
+                .keys
+                .iter()
+                .filter(|k| !excluded.contains(k) && self.state.key_health.is_available(k))
+                .collect(),
+            key_usage: &key_usage,
+            key_health: &self.state.key_health,
+            client_token,
+        };
+
+        // 按提供者配置的策略选择密钥
+        self.state
+            .key_selectors
+            .get(provider.key_selection)
+            .select(&ctx)
+            .cloned()
+            .ok_or_else(|| {
+                AppError::UpstreamUnavailable(format!(
+                    "No available API key left for provider '{}'",
+                    provider.name
+                ))
+            })
     }
 
     async fn update_usage_stats(&self, provider: &Provider, api_key: &str) {
@@ -64,90 +163,603 @@ impl AIService {
         );
     }
 
+    /// 向单个转发目标发送请求，失败时在该提供者的密钥之间切换重试
+    async fn forward_to_target(
+        &self,
+        target: &RouteTarget,
+        payload: &Value,
+        endpoint_type: EndpointType,
+        client_options: ResponseOptions,
+        client_token: Option<&str>,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse, TargetError> {
+        let provider = &target.provider;
+        let target_id = target.id();
+
+        // 按提供者配置筛选透传的客户端请求头
+        let forwarded =
+            headers::forwardable_request_headers(client_headers, provider.forward_headers.as_ref());
+
+        // 根据端点类型选择URL
+        let url = match endpoint_type {
+            EndpointType::Completions | EndpointType::Messages => {
+                provider.endpoints.completions.as_ref()
+            }
+            EndpointType::Embeddings => provider.endpoints.embeddings.as_ref(),
+        };
+        let url = url.ok_or_else(|| {
+            // 端点不受支持时可以继续尝试下一个目标
+            TargetError::retryable(AppError::EndpointNotSupported(format!(
+                "Provider '{}' does not support {} endpoint",
+                provider.name,
+                endpoint_type.name()
+            )))
+        })?;
+
+        // 按提供者类型生成最终的请求地址
+        let options = client_options.for_provider(provider);
+        let url = adapters::request_url(provider, url, &target.model, endpoint_type, options);
+
+        // 按提供者类型转换请求体，并替换为上游真实模型名称
+        let body =
+            adapters::convert_request(provider, &target.model, payload, endpoint_type, options)
+                .map_err(TargetError::retryable)?;
+
+        // 失败时依次换用其他密钥重试，直到用完尝试次数
+        let max_attempts = provider.max_attempts.unwrap_or(provider.keys.len()).max(1);
+        let mut tried_keys: Vec<String> = Vec::new();
+        let mut last_error = None;
+
+        for attempt in 1..=max_attempts {
+            // 所有密钥都已尝试过时，重新从全部密钥中选择
+            if tried_keys.len() >= provider.keys.len() {
+                tried_keys.clear();
+            }
+
+            // 选择API密钥，没有可用密钥时回退到下一个目标
+            let api_key = match self
+                .select_api_key(provider, &tried_keys, client_token)
+                .await
+            {
+                Ok(api_key) => api_key,
+                Err(e) => return Err(TargetError::retryable(last_error.unwrap_or(e))),
+            };
+
+            // 直接转发请求并返回流式响应
+            let in_flight = self.state.load_balancer.start_request(&target_id);
+            let started_at = Instant::now();
+            let mut request = adapters::build_request(
+                &self.state.http_client,
+                provider,
+                &url,
+                &target.model,
+                &api_key,
+                &body,
+                forwarded.clone(),
+            );
+            if let Some(total) = provider.timeouts.total() {
+                request = request.timeout(total);
+            }
+            let result = match provider.timeouts.first_byte() {
+                Some(limit) => tokio::time::timeout(limit, request.send()).await,
+                None => Ok(request.send().await),
+            };
+
+            match result {
+                Ok(Ok(resp)) => {
+                    // 更新使用统计
+                    self.update_usage_stats(provider, &api_key).await;
+                    self.state
+                        .metrics
+                        .observe_upstream_latency(&provider.name, started_at.elapsed());
+
+                    let status = resp.status();
+                    if status.is_success() {
+                        self.state.key_health.record_success(&api_key);
+                        self.state
+                            .load_balancer
+                            .record_latency(&target_id, started_at.elapsed());
+                        return Ok(UpstreamResponse {
+                            response: resp,
+                            in_flight,
+                            api_key,
+                        });
+                    }
+
+                    // 上游过载或出错时按惩罚延迟计入 EWMA，密钥问题与目标本身的延迟无关
+                    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        self.state
+                            .load_balancer
+                            .record_failure(&target_id, started_at.elapsed());
+                    }
+                    let response_headers = resp.headers().clone();
+                    let error_text = resp
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Unknown error".to_string());
+                    error!(
+                        "API request to provider '{}' failed (attempt {}/{}): {} - {}",
+                        provider.name, attempt, max_attempts, status, error_text
+                    );
+                    self.state.key_health.record_failure(
+                        &api_key,
+                        status,
+                        &response_headers,
+                        &error_text,
+                    );
+
+                    // Gemini 对无效密钥返回 400，与 401 一样换用其他密钥重试
+                    let status = if status == reqwest::StatusCode::BAD_REQUEST
+                        && is_invalid_key_error(&error_text)
+                    {
+                        reqwest::StatusCode::UNAUTHORIZED
+                    } else {
+                        status
+                    };
+                    let error = adapters::convert_error(
+                        provider.kind,
+                        status,
+                        &response_headers,
+                        &error_text,
+                    );
+
+                    if !is_retryable_status(status) {
+                        return Err(TargetError::fatal(error));
+                    }
+                    last_error = Some(error);
+                }
+                Ok(Err(e)) => {
+                    // 请求地址可能带有密钥，不记录
+                    let e = e.without_url();
+                    error!(
+                        "API request to provider '{}' error (attempt {}/{}): {}",
+                        provider.name, attempt, max_attempts, e
+                    );
+                    self.state
+                        .load_balancer
+                        .record_failure(&target_id, started_at.elapsed());
+                    if e.is_timeout() {
+                        self.state
+                            .timeout_stats
+                            .record(&provider.name, TimeoutKind::Total);
+                    }
+                    if !is_retryable_error(&e) {
+                        return Err(TargetError::fatal(AppError::Http(e)));
+                    }
+                    last_error = Some(AppError::Http(e));
+                }
+                Err(_) => {
+                    // 首字节超时通常是上游整体过载，不再换用同一提供者的其他密钥
+                    let limit = provider.timeouts.first_byte_secs.unwrap_or_default();
+                    error!(
+                        "API request to provider '{}' got no response within {}s (attempt {}/{})",
+                        provider.name, limit, attempt, max_attempts
+                    );
+                    self.state
+                        .timeout_stats
+                        .record(&provider.name, TimeoutKind::FirstByte);
+                    self.state
+                        .load_balancer
+                        .record_failure(&target_id, started_at.elapsed());
+                    return Err(TargetError::retryable(AppError::UpstreamTimeout(format!(
+                        "Provider '{}' did not respond within {}s",
+                        provider.name, limit
+                    ))));
+                }
+            }
+
+            if attempt < max_attempts {
+                warn!(
+                    "Retrying request for provider '{}' with another API key",
+                    provider.name
+                );
+                self.state.metrics.record_retry(&provider.name);
+            }
+            tried_keys.push(api_key);
+        }
+
+        Err(TargetError::retryable(last_error.unwrap_or_else(|| {
+            AppError::UpstreamUnavailable("API request failed".to_string())
+        })))
+    }
+
+    /// 转发请求，失败时返回 OpenAI 格式的错误响应，同样带有请求 ID 和处理请求的提供者
     pub async fn forward_request_with_model_replacement(
         &self,
-        mut payload: Value,
+        payload: Value,
+        model: String,
+        headers: HeaderMap,
+        client: ClientIdentity,
+        endpoint_type: EndpointType,
+    ) -> Response {
+        // 本次转发的请求 ID，返回给客户端用于排查问题，并写入审计日志
+        let record = AuditRecord::new(
+            Uuid::new_v4().to_string(),
+            client.name.clone(),
+            client.ip.clone(),
+            endpoint_type.name(),
+            model.clone(),
+            ResponseOptions::from_payload(&payload).stream,
+        );
+
+        let (capture, metric_alias) = {
+            let config = self.state.config.read().await;
+            let capture = Capture::start(
+                self.state.capture_log.as_ref(),
+                config.capture.as_ref(),
+                &record.request_id,
+                &client.name,
+                &model,
+                &payload,
+            );
+            let metric_alias = if config.is_configured_model(&model) {
+                model.clone()
+            } else {
+                "unknown".to_string()
+            };
+            (capture, metric_alias)
+        };
+
+        let mut trace = RequestTrace {
+            audit: record,
+            capture,
+            metric_alias,
+        };
+
+        let result = self
+            .forward_request(payload, model, headers, client, endpoint_type, &mut trace)
+            .await;
+
+        let status = match &result {
+            Ok(response) => response.status().as_u16(),
+            Err(e) => e.status().as_u16(),
+        };
+        self.state.metrics.record_request(
+            &trace.metric_alias,
+            trace.audit.provider.as_deref(),
+            status,
+        );
+
+        // 成功的请求在响应体传输结束后记录，失败的请求在这里记录
+        let e = match result {
+            Ok(response) => return response,
+            Err(e) => e,
+        };
+        let RequestTrace {
+            audit: mut record,
+            capture,
+            ..
+        } = trace;
+        record.status = e.status().as_u16();
+        record.error_code = Some(e.code().as_str());
+        record.latency_ms = record.elapsed_ms();
+        if let Some(capture) = capture {
+            capture.finish_error(e.status().as_u16(), json!({"error": e.to_string()}));
+        }
+
+        let mut response = e.into_response();
+        let response_headers = response.headers_mut();
+        if let Some(value) = record
+            .provider
+            .as_deref()
+            .and_then(|provider| HeaderValue::from_str(provider).ok())
+        {
+            response_headers.insert(headers::PROVIDER_HEADER, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&record.request_id) {
+            response_headers.insert(headers::REQUEST_ID_HEADER, value);
+        }
+        self.state.audit(record);
+        response
+    }
+
+    async fn forward_request(
+        &self,
+        payload: Value,
         model: String,
-        _headers: HeaderMap,
+        headers: HeaderMap,
+        client: ClientIdentity,
         endpoint_type: EndpointType,
+        trace: &mut RequestTrace,
     ) -> AppResult<Response> {
-        // 查找提供者
-        let provider = self
-            .state
-            .get_provider_by_model(&model)
-            .await
-            .ok_or_else(|| AppError::Validation(format!("Model '{}' not found", model)))?;
+        let RequestTrace {
+            audit: record,
+            capture,
+            metric_alias,
+        } = trace;
+        // 客户端令牌，用于粘性密钥选择
+        let client_token = extract_token(&headers);
 
-        // 获取真实模型名称
-        let real_model = self.state.get_model_mapping(&model).await.ok_or_else(|| {
-            AppError::Validation(format!("Model mapping not found for '{}'", model))
-        })?;
+        // 在解析转发目标之前检查模型访问权限
+        if !client.model_acl.allows(&model) {
+            return Err(AppError::ModelNotAllowed(format!(
+                "Client '{}' is not allowed to access model '{}'",
+                client.name, model
+            )));
+        }
 
-        // 只替换payload中的model字段
-        payload["model"] = Value::String(real_model);
+        let request_id = record.request_id.clone();
 
-        // 选择API密钥
-        let api_key = self.select_api_key(&provider).await?;
+        // 查找该别名对应的所有转发目标
+        let targets = self.state.get_route_targets(&model).await;
+        if targets.is_empty() {
+            return Err(AppError::ModelNotFound(format!(
+                "Model '{}' not found",
+                model
+            )));
+        }
 
-        // 根据端点类型选择URL
-        let url = match endpoint_type {
-            EndpointType::Completions => {
-                provider.endpoints.completions.as_ref().ok_or_else(|| {
-                    AppError::Validation(format!(
-                        "Provider '{}' does not support completions endpoint",
-                        provider.name
-                    ))
-                })?
+        // 别名可能指向被禁止的上游模型，跳过这些目标
+        let targets: Vec<RouteTarget> = targets
+            .into_iter()
+            .filter(|target| {
+                client
+                    .model_acl
+                    .allows_target(&target.provider.name, &target.model)
+            })
+            .collect();
+        if targets.is_empty() {
+            return Err(AppError::ModelNotAllowed(format!(
+                "Client '{}' is not allowed to access model '{}'",
+                client.name, model
+            )));
+        }
+
+        // stream_options 必须是对象，转发时可能需要在其中注入 include_usage
+        if !matches!(
+            payload.get("stream_options"),
+            None | Some(Value::Null) | Some(Value::Object(_))
+        ) {
+            return Err(AppError::Validation(
+                "'stream_options' must be an object".to_string(),
+            ));
+        }
+
+        // 检查全局、客户端和模型别名的限流，许可持续到响应体传输结束
+        let scopes = {
+            let config = self.state.config.read().await;
+            let mut scopes = Vec::new();
+            if let Some(limit) = config.rate_limit {
+                scopes.push(RateLimitScope {
+                    key: "global".to_string(),
+                    limit,
+                });
             }
-            EndpointType::Embeddings => {
-                provider.endpoints.embeddings.as_ref().ok_or_else(|| {
-                    AppError::Validation(format!(
-                        "Provider '{}' does not support embeddings endpoint",
-                        provider.name
-                    ))
-                })?
+            if let Some(limit) = client.rate_limit {
+                scopes.push(RateLimitScope {
+                    key: format!("client:{}", client.name),
+                    limit,
+                });
             }
+            if let Some((alias, limit)) = config.model_rate_limit(&model) {
+                scopes.push(RateLimitScope {
+                    key: format!("model:{}", alias),
+                    limit,
+                });
+            }
+            scopes
         };
-
-        // 直接转发请求并返回流式响应
-        let response = self
+        let permit = self
             .state
-            .http_client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", api_key))
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
-            .await?;
-
-        // 更新使用统计
-        self.update_usage_stats(&provider, &api_key).await;
-
-        // 检查响应状态
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
+            .rate_limiter
+            .acquire(scopes)
+            .map_err(|rejection| {
+                warn!(
+                    "Rate limited request from client '{}': {}",
+                    client.name, rejection.message
+                );
+                AppError::RateLimited {
+                    message: rejection.message,
+                    retry_after: Some(rejection.retry_after.as_secs_f64().ceil().max(1.0) as u64),
+                    headers: Box::new(rejection.status.headers()),
+                }
+            })?;
+
+        // 按顺序尝试每个目标，当前目标出错或超时则回退到下一个
+        let mut last_error = None;
+        let mut response = None;
+        let client_options = ResponseOptions::from_payload(&payload);
+
+        for (index, target) in targets.iter().enumerate() {
+            match self
+                .forward_to_target(
+                    target,
+                    &payload,
+                    endpoint_type,
+                    client_options,
+                    client_token,
+                    &headers,
+                )
                 .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            error!("API request failed: {} - {}", status, error_text);
-            return Err(AppError::Internal(format!(
-                "API request failed: {}",
-                status
-            )));
+            {
+                Ok(upstream) => {
+                    response = Some((upstream, target));
+                    break;
+                }
+                Err(TargetError {
+                    error,
+                    retryable: false,
+                }) => {
+                    record.provider = Some(target.provider.name.clone());
+                    record.model = Some(target.model.clone());
+                    return Err(error);
+                }
+                Err(TargetError { error, .. }) => {
+                    // 所有目标都失败时返回最后一个目标的错误
+                    record.provider = Some(target.provider.name.clone());
+                    record.model = Some(target.model.clone());
+                    if index + 1 < targets.len() {
+                        warn!(
+                            "Provider '{}' failed for model '{}': {}, falling back to next provider",
+                            target.provider.name, model, error
+                        );
+                        self.state
+                            .metrics
+                            .record_failover(metric_alias, &target.provider.name);
+                    }
+                    last_error = Some(error);
+                }
+            }
         }
 
-        // 获取响应头
-        let mut response_headers = HeaderMap::new();
-        for (key, value) in response.headers() {
-            if let Ok(header_name) = axum::http::HeaderName::from_bytes(key.as_str().as_bytes()) {
-                response_headers.insert(header_name, value.clone());
+        let (
+            UpstreamResponse {
+                response,
+                in_flight,
+                api_key,
+            },
+            target,
+        ) = match response {
+            Some(upstream) => upstream,
+            None => {
+                return Err(last_error.unwrap_or_else(|| {
+                    AppError::UpstreamUnavailable("API request failed".to_string())
+                }))
             }
+        };
+        let provider = &target.provider;
+        let kind = provider.kind;
+        let options = client_options.for_provider(provider);
+        record.provider = Some(provider.name.clone());
+        record.model = Some(target.model.clone());
+        record.key_fingerprint = Some(persistence::key_fingerprint(&api_key));
+        debug!(
+            "Request {} for model '{}' served by provider '{}'",
+            request_id, model, provider.name
+        );
+
+        // 按响应头策略筛选上游响应头，并标明处理请求的提供者和请求 ID
+        let (header_policy, budget_alerts) = {
+            let config = self.state.config.read().await;
+            let budget_alerts = config
+                .budget
+                .as_ref()
+                .map(|budget| budget.daily_alerts.clone())
+                .unwrap_or_default();
+            (config.response_headers.clone(), budget_alerts)
+        };
+        let mut response_headers =
+            headers::sanitize_response_headers(response.headers(), header_policy.as_ref());
+        if let Ok(value) = HeaderValue::from_str(&provider.name) {
+            response_headers.insert(headers::PROVIDER_HEADER, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response_headers.insert(headers::REQUEST_ID_HEADER, value);
         }
+        response_headers.extend(permit.status.headers());
 
-        // 直接使用响应的字节流
+        // 响应体被转换过时，原有的类型不再适用
+        if adapters::translates_response(kind, endpoint_type) {
+            let content_type = if options.stream {
+                "text/event-stream"
+            } else {
+                "application/json"
+            };
+            response_headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        // 使用转换后的响应字节流，在途计数持续到响应体传输结束
         let status = response.status();
-        let body = Body::from_stream(response.bytes_stream());
+        record.status = status.as_u16();
+        let upstream = timeouts::guard_body(
+            response.bytes_stream(),
+            provider.name.clone(),
+            provider.timeouts.idle(),
+            self.state.timeout_stats.clone(),
+        );
+        let stream = adapters::convert_response(kind, endpoint_type, upstream, options).await?;
+
+        // 记录返回第一个数据块的时间
+        let first_chunk = Arc::new(OnceLock::new());
+        let stream = {
+            let first_chunk = first_chunk.clone();
+            let started = record.started;
+            stream
+                .map(move |chunk| {
+                    first_chunk.get_or_init(|| started.elapsed());
+                    chunk
+                })
+                .boxed()
+        };
+
+        // 开启了捕获时复制响应体，流式响应包含代理注入的用量块
+        let stream = match capture.take() {
+            Some(mut capture) => {
+                capture.set_upstream(&provider.name, &target.model, status.as_u16());
+                capture.tee(stream, endpoint_type, options.stream)
+            }
+            None => stream,
+        };
+
+        // 响应体传输结束后记录 token 用量和费用
+        let pricing = target.pricing;
+        let labels = UsageLabels {
+            provider: provider.name.clone(),
+            alias: model,
+            key: persistence::key_fingerprint(&api_key),
+            client: client.name,
+        };
+        let usage_tracker = self.state.usage_tracker.clone();
+        let quota_manager = self.state.quota_manager.clone();
+        let state = self.state.clone();
+        let mut record = record.clone();
+        let metric_alias = metric_alias.clone();
+        // 代理为统计用量而要求的用量块不返回给没有请求它的客户端，Anthropic 格式的响应总是带有用量
+        let hide_usage = matches!(endpoint_type, EndpointType::Completions)
+            && options.include_usage
+            && !client_options.include_usage;
+        let stream = usage::tap_usage(
+            stream,
+            endpoint_type,
+            options.stream,
+            hide_usage,
+            Box::new(move |usage| {
+                // 许可随回调一起释放，并发计数持续到响应体传输结束
+                let permit = permit;
+                record.latency_ms = record.elapsed_ms();
+                record.ttft_ms = first_chunk.get().map(|d| d.as_millis() as u64);
+                if let Some(first_byte) = first_chunk.get() {
+                    state
+                        .metrics
+                        .observe_first_byte(&labels.provider, *first_byte);
+                }
+
+                let Some(usage) = usage else {
+                    warn!(
+                        "No token usage found in response from provider '{}' for request {}, tokens and cost were not recorded",
+                        labels.provider, record.request_id
+                    );
+                    state.audit(record);
+                    return;
+                };
+                permit.record_tokens(usage.total_tokens);
+                state
+                    .metrics
+                    .record_tokens(&metric_alias, &labels.provider, &usage);
+                let cost = pricing.map(|p| usage.cost(&p));
+                let (before, after) =
+                    usage_tracker.record(&labels, usage, cost.unwrap_or_default());
+                quota_manager.record(&labels.client, usage.total_tokens, cost.unwrap_or_default());
+                for threshold in budget_alerts
+                    .iter()
+                    .filter(|t| before < **t && after >= **t)
+                {
+                    warn!(
+                        "Daily spend ${:.4} crossed budget alert threshold ${}",
+                        after, threshold
+                    );
+                }
+
+                record.usage = Some(usage);
+                record.cost = cost;
+                state.audit(record);
+            }),
+        );
+        let body = Body::from_stream(stream.map(move |chunk| {
+            let _ = &in_flight;
+            chunk
+        }));
 
         // 构建响应
         let mut axum_response = Response::builder().status(status);
@@ -166,6 +778,43 @@ impl AIService {
 
     pub async fn get_usage_stats(&self) -> AppResult<Value> {
         let provider_usage = self.state.provider_usage.read().await;
+        let config = self.state.config.read().await;
+
+        // 各提供者密钥的健康状态，密钥只展示脱敏后的形式
+        let key_health: Vec<Value> = config
+            .providers
+            .iter()
+            .flat_map(|provider| {
+                provider.keys.iter().map(|key| {
+                    let mut entry = json!({
+                        "provider": provider.name,
+                        "key": mask_key(key),
+                    });
+                    match self.state.key_health.health(key) {
+                        KeyHealth::Healthy => entry["status"] = json!("healthy"),
+                        KeyHealth::RateLimited { until } => {
+                            let remaining = until.saturating_duration_since(Instant::now());
+                            entry["status"] = json!("rate_limited");
+                            entry["cooldown_remaining_secs"] = json!(remaining.as_secs());
+                        }
+                        KeyHealth::Quarantined { until, reason } => {
+                            let remaining = until.saturating_duration_since(Instant::now());
+                            entry["status"] = json!("quarantined");
+                            entry["cooldown_remaining_secs"] = json!(remaining.as_secs());
+                            entry["reason"] = json!(reason);
+                        }
+                        KeyHealth::Disabled { reason } => {
+                            entry["status"] = json!("disabled");
+                            entry["reason"] = json!(reason);
+                        }
+                    }
+                    if let Some(at) = self.state.key_health.last_rate_limited(key) {
+                        entry["last_rate_limited_at"] = json!(at.to_rfc3339());
+                    }
+                    entry
+                })
+            })
+            .collect();
 
         Ok(json!({
             "provider_usage": provider_usage.iter().map(|entry| {
@@ -173,7 +822,211 @@ impl AIService {
                     "provider": entry.key(),
                     "usage": *entry.value()
                 })
+            }).collect::<Vec<_>>(),
+            "key_health": key_health,
+            "token_usage": self.state.usage_tracker.snapshot(),
+            "quotas": self.state.quota_manager.snapshot_all(&config.clients),
+            "timeouts": self.state.timeout_stats.snapshot().into_iter().map(|(provider, counts)| {
+                json!({
+                    "provider": provider,
+                    "timeouts": counts
+                })
             }).collect::<Vec<_>>()
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::services::acl::ModelAcl;
+    use axum::{body::to_bytes, extract::State, http::StatusCode, routing::post, Json, Router};
+    use std::sync::Mutex;
+
+    /// 模拟上游，记录每次请求使用的密钥，并按密钥返回对应的状态码
+    #[derive(Clone, Default)]
+    struct MockUpstream {
+        hits: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn mock_completions(
+        State(upstream): State<MockUpstream>,
+        headers: HeaderMap,
+    ) -> Response {
+        let key = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .to_string();
+        upstream.hits.lock().unwrap().push(key.clone());
+
+        let status = match key.split('-').next().unwrap_or_default() {
+            "limited" => StatusCode::TOO_MANY_REQUESTS,
+            "broken" => StatusCode::INTERNAL_SERVER_ERROR,
+            "invalid" => StatusCode::BAD_REQUEST,
+            _ => StatusCode::OK,
+        };
+        if !status.is_success() {
+            let error = json!({"error": {"message": format!("failed with {}", key)}});
+            return (status, Json(error)).into_response();
+        }
+        Json(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": key}}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+        }))
+        .into_response()
+    }
+
+    /// 启动模拟上游，返回 completions 地址
+    async fn start_upstream(upstream: MockUpstream) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route("/v1/chat/completions", post(mock_completions))
+            .with_state(upstream);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1/chat/completions", addr)
+    }
+
+    async fn service(providers: Value) -> AIService {
+        let config: Config = serde_json::from_value(json!({
+            "auth": "tok",
+            "port": 0,
+            "providers": providers
+        }))
+        .unwrap();
+        AIService::new(AppState::new(config).await.unwrap())
+    }
+
+    fn provider(name: &str, url: &str, keys: &[&str], priority: u32) -> Value {
+        json!({
+            "name": name,
+            "models": [{"alias": "gpt-4", "model": "gpt-4-0613", "priority": priority}],
+            "endpoints": {"completions": url},
+            "keys": keys
+        })
+    }
+
+    async fn send(service: &AIService) -> (StatusCode, HeaderMap, Value) {
+        let client = ClientIdentity {
+            name: "test".to_string(),
+            model_acl: ModelAcl::default(),
+            rate_limit: None,
+            ip: "127.0.0.1".to_string(),
+        };
+        let payload = json!({"model": "gpt-4", "messages": [{"role": "user", "content": "hi"}]});
+        let response = service
+            .forward_request_with_model_replacement(
+                payload,
+                "gpt-4".to_string(),
+                HeaderMap::new(),
+                client,
+                EndpointType::Completions,
+            )
+            .await;
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            serde_json::from_slice(&body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        for status in [401, 403, 429, 500, 502, 503] {
+            assert!(is_retryable_status(
+                reqwest::StatusCode::from_u16(status).unwrap()
+            ));
+        }
+        for status in [400, 404, 422] {
+            assert!(!is_retryable_status(
+                reqwest::StatusCode::from_u16(status).unwrap()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_with_another_key_after_rate_limit() {
+        let upstream = MockUpstream::default();
+        let url = start_upstream(upstream.clone()).await;
+        let service = service(json!([provider("openai", &url, &["limited-1", "ok-1"], 0)])).await;
+
+        let (status, _, body) = send(&service).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["choices"][0]["message"]["content"], "ok-1");
+        assert_eq!(*upstream.hits.lock().unwrap(), ["limited-1", "ok-1"]);
+        assert!(!matches!(
+            service.state.key_health.health("limited-1"),
+            KeyHealth::Healthy
+        ));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let upstream = MockUpstream::default();
+        let url = start_upstream(upstream.clone()).await;
+        let service = service(json!([provider("openai", &url, &["invalid-1", "ok-1"], 0)])).await;
+
+        let (status, _, body) = send(&service).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["message"], "failed with invalid-1");
+        assert_eq!(*upstream.hits.lock().unwrap(), ["invalid-1"]);
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_when_all_keys_fail() {
+        let upstream = MockUpstream::default();
+        let url = start_upstream(upstream.clone()).await;
+        let service = service(json!([provider(
+            "openai",
+            &url,
+            &["broken-1", "broken-2"],
+            0
+        )]))
+        .await;
+
+        let (status, _, body) = send(&service).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["message"], "failed with broken-2");
+        assert_eq!(upstream.hits.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_provider_by_priority() {
+        let upstream = MockUpstream::default();
+        let url = start_upstream(upstream.clone()).await;
+        let service = service(json!([
+            provider("backup", &url, &["ok-backup"], 1),
+            provider("primary", &url, &["broken-1"], 0)
+        ]))
+        .await;
+
+        let (status, response_headers, body) = send(&service).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response_headers[headers::PROVIDER_HEADER], "backup");
+        assert_eq!(body["choices"][0]["message"]["content"], "ok-backup");
+        assert_eq!(*upstream.hits.lock().unwrap(), ["broken-1", "ok-backup"]);
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_on_client_errors() {
+        let upstream = MockUpstream::default();
+        let url = start_upstream(upstream.clone()).await;
+        let service = service(json!([
+            provider("primary", &url, &["invalid-1"], 0),
+            provider("backup", &url, &["ok-backup"], 1)
+        ]))
+        .await;
+
+        let (status, response_headers, _) = send(&service).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response_headers[headers::PROVIDER_HEADER], "primary");
+        assert_eq!(*upstream.hits.lock().unwrap(), ["invalid-1"]);
+    }
+}
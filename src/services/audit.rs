@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use tracing::warn;
+
+use crate::config::AuditLogConfig;
+use crate::logger::rotating_file;
+use crate::services::usage::TokenUsage;
+
+/// 一次转发请求的审计记录
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    /// 客户端名称
+    pub client: String,
+    pub client_ip: String,
+    /// 请求的端点，如 `completions`、`embeddings`
+    pub endpoint: &'static str,
+    /// 客户端请求的模型别名
+    pub alias: String,
+    /// 实际处理请求的提供者，请求没有到达上游时为空
+    pub provider: Option<String>,
+    /// 上游真实模型名称
+    pub model: Option<String>,
+    /// 上游密钥指纹
+    pub key_fingerprint: Option<String>,
+    pub stream: bool,
+    /// 返回给客户端的状态码
+    pub status: u16,
+    /// 失败时的错误码
+    pub error_code: Option<&'static str>,
+    /// 从收到请求到响应体传输结束的耗时
+    pub latency_ms: u64,
+    /// 从收到请求到返回第一个数据块的耗时
+    pub ttft_ms: Option<u64>,
+    pub usage: Option<TokenUsage>,
+    /// 估算费用（美元），模型未配置价格时为空
+    pub cost: Option<f64>,
+    /// 收到请求的时间，用于计算耗时
+    #[serde(skip)]
+    pub started: Instant,
+}
+
+impl AuditRecord {
+    pub fn new(
+        request_id: String,
+        client: String,
+        client_ip: String,
+        endpoint: &'static str,
+        alias: String,
+        stream: bool,
+    ) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            request_id,
+            client,
+            client_ip,
+            endpoint,
+            alias,
+            provider: None,
+            model: None,
+            key_fingerprint: None,
+            stream,
+            status: 0,
+            error_code: None,
+            latency_ms: 0,
+            ttft_ms: None,
+            usage: None,
+            cost: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// 从收到请求到现在经过的毫秒数
+    pub fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+/// 审计日志，每行一条 JSON 记录，在后台线程写入按大小轮转的文件
+pub struct AuditLogger {
+    sender: mpsc::Sender<AuditRecord>,
+}
+
+impl AuditLogger {
+    pub fn new(config: &AuditLogConfig) -> Self {
+        let mut writer = rotating_file(&config.file, config.max_files, config.max_file_size);
+
+        let (sender, receiver) = mpsc::channel::<AuditRecord>();
+        thread::spawn(move || {
+            for record in receiver {
+                let result = serde_json::to_string(&record)
+                    .map_err(std::io::Error::from)
+                    .and_then(|line| writeln!(writer, "{}", line));
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to write audit record for request {}: {}",
+                        record.request_id, e
+                    );
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// 记录一次请求，写入在后台进行，不阻塞请求处理
+    pub fn log(&self, record: AuditRecord) {
+        let _ = self.sender.send(record);
+    }
+}
@@ -0,0 +1,224 @@
+use dashmap::DashMap;
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::BalanceStrategy;
+use crate::state::RouteTarget;
+
+/// EWMA 平滑系数，越大越偏向最新的延迟样本
+const EWMA_ALPHA: f64 = 0.3;
+
+/// 失败请求计入 EWMA 的最小延迟，避免快速失败的目标因延迟低而被优先选择
+const FAILURE_PENALTY: Duration = Duration::from_secs(10);
+
+/// 多个提供者之间的负载均衡器
+///
+/// 记录每个转发目标的在途请求数和延迟，按策略决定同一优先级内目标的尝试顺序
+#[derive(Default)]
+pub struct LoadBalancer {
+    /// 每个别名的轮询计数器
+    round_robin: DashMap<String, AtomicUsize>,
+    /// 每个目标的在途请求数
+    in_flight: DashMap<String, Arc<AtomicU64>>,
+    /// 每个目标的延迟 EWMA（毫秒）
+    latency_ewma: DashMap<String, f64>,
+}
+
+/// 在途请求计数守卫，释放时自动减少计数
+pub struct InFlightGuard {
+    counter: Arc<AtomicU64>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按策略对目标排序，优先级仍然优先于策略
+    pub fn order(
+        &self,
+        alias: &str,
+        strategy: BalanceStrategy,
+        targets: Vec<RouteTarget>,
+    ) -> Vec<RouteTarget> {
+        let mut ordered = Vec::with_capacity(targets.len());
+        let mut remaining = targets.into_iter().peekable();
+
+        // targets 已经按优先级排好序，逐组处理
+        while let Some(first) = remaining.next() {
+            let priority = first.priority;
+            let mut group = vec![first];
+            while let Some(next) = remaining.next_if(|t| t.priority == priority) {
+                group.push(next);
+            }
+            ordered.extend(self.order_group(alias, strategy, group));
+        }
+
+        ordered
+    }
+
+    fn order_group(
+        &self,
+        alias: &str,
+        strategy: BalanceStrategy,
+        mut group: Vec<RouteTarget>,
+    ) -> Vec<RouteTarget> {
+        if group.len() <= 1 {
+            return group;
+        }
+
+        match strategy {
+            BalanceStrategy::Priority => group,
+            BalanceStrategy::RoundRobin => {
+                let counter = self
+                    .round_robin
+                    .entry(alias.to_string())
+                    .or_insert_with(|| AtomicUsize::new(0));
+                let start = counter.fetch_add(1, Ordering::Relaxed) % group.len();
+                group.rotate_left(start);
+                group
+            }
+            BalanceStrategy::WeightedRandom => {
+                // 按权重不放回抽样，得到完整的尝试顺序
+                let mut rng = rand::rng();
+                let mut ordered = Vec::with_capacity(group.len());
+                while !group.is_empty() {
+                    let total: u64 = group.iter().map(|t| t.weight as u64).sum();
+                    let index = if total == 0 {
+                        rng.random_range(0..group.len())
+                    } else {
+                        let mut point = rng.random_range(0..total);
+                        group
+                            .iter()
+                            .position(|t| {
+                                if point < t.weight as u64 {
+                                    true
+                                } else {
+                                    point -= t.weight as u64;
+                                    false
+                                }
+                            })
+                            .unwrap_or(0)
+                    };
+                    ordered.push(group.remove(index));
+                }
+                ordered
+            }
+            BalanceStrategy::LeastInFlight => {
+                group.sort_by_key(|t| self.in_flight_count(&t.id()));
+                group
+            }
+            BalanceStrategy::EwmaLatency => {
+                // 没有延迟数据的目标视为 0，保证新目标能被探测到
+                group.sort_by(|a, b| {
+                    let a = self.latency(&a.id()).unwrap_or(0.0);
+                    let b = self.latency(&b.id()).unwrap_or(0.0);
+                    a.total_cmp(&b)
+                });
+                group
+            }
+        }
+    }
+
+    /// 开始一个在途请求，返回的守卫释放时结束计数
+    pub fn start_request(&self, target_id: &str) -> InFlightGuard {
+        let counter = self
+            .in_flight
+            .entry(target_id.to_string())
+            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .clone();
+        counter.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { counter }
+    }
+
+    /// 记录一次上游响应延迟
+    pub fn record_latency(&self, target_id: &str, latency: Duration) {
+        self.record_sample(target_id, latency);
+    }
+
+    /// 记录一次失败的上游请求，按实际耗时和 `FAILURE_PENALTY` 中较大者计入延迟
+    pub fn record_failure(&self, target_id: &str, elapsed: Duration) {
+        self.record_sample(target_id, elapsed.max(FAILURE_PENALTY));
+    }
+
+    fn record_sample(&self, target_id: &str, latency: Duration) {
+        let sample = latency.as_secs_f64() * 1000.0;
+        self.latency_ewma
+            .entry(target_id.to_string())
+            .and_modify(|ewma| *ewma = EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * *ewma)
+            .or_insert(sample);
+    }
+
+    pub fn in_flight_count(&self, target_id: &str) -> u64 {
+        self.in_flight
+            .get(target_id)
+            .map(|c| c.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// 所有目标的在途请求数
+    pub fn in_flight_snapshot(&self) -> Vec<(String, u64)> {
+        self.in_flight
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn latency(&self, target_id: &str) -> Option<f64> {
+        self.latency_ewma.get(target_id).map(|v| *v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewma_latency_smooths_samples() {
+        let balancer = LoadBalancer::new();
+        balancer.record_latency("a", Duration::from_millis(100));
+        assert_eq!(balancer.latency("a"), Some(100.0));
+
+        balancer.record_latency("a", Duration::from_millis(200));
+        let latency = balancer.latency("a").unwrap();
+        assert!((latency - 130.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn failures_are_penalized_in_ewma() {
+        let balancer = LoadBalancer::new();
+        balancer.record_latency("fast", Duration::from_millis(100));
+        balancer.record_latency("failing", Duration::from_millis(50));
+
+        // 快速失败也按惩罚延迟计入，之后不再排在正常目标前面
+        balancer.record_failure("failing", Duration::from_millis(5));
+        assert!(balancer.latency("failing").unwrap() > balancer.latency("fast").unwrap());
+
+        balancer.record_failure("new", Duration::from_millis(5));
+        assert_eq!(
+            balancer.latency("new"),
+            Some(FAILURE_PENALTY.as_secs_f64() * 1000.0)
+        );
+    }
+
+    #[test]
+    fn in_flight_guard_decrements_on_drop() {
+        let balancer = LoadBalancer::new();
+        let first = balancer.start_request("a");
+        let second = balancer.start_request("a");
+        assert_eq!(balancer.in_flight_count("a"), 2);
+
+        drop(first);
+        assert_eq!(balancer.in_flight_count("a"), 1);
+        drop(second);
+        assert_eq!(balancer.in_flight_count("a"), 0);
+    }
+}
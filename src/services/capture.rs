@@ -0,0 +1,539 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tracing::warn;
+
+use crate::config::CaptureConfig;
+use crate::logger::rotating_file;
+use crate::services::acl::glob_match;
+use crate::services::adapters::ByteStream;
+use crate::services::ai::EndpointType;
+
+/// 脱敏后的字段值
+const REDACTED: &str = "[REDACTED]";
+
+/// 一次请求的捕获记录
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureRecord {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    pub client: String,
+    pub alias: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub status: u16,
+    /// 客户端发送的请求体
+    pub request: Value,
+    /// 返回给客户端的响应体，流式响应会被重组为完整的 `chat.completion` 或 Anthropic message 对象
+    pub response: Value,
+    /// 响应体超过 `max_body_bytes` 时为 true，此时 `response` 为截断的原始文本
+    pub truncated: bool,
+}
+
+/// 等待后台线程处理的响应体
+enum CapturedBody {
+    Json(Vec<u8>),
+    Sse(Vec<u8>),
+    AnthropicSse(Vec<u8>),
+    Truncated(Vec<u8>),
+    Value(Value),
+}
+
+struct CaptureJob {
+    record: CaptureRecord,
+    body: CapturedBody,
+    redact: Vec<String>,
+}
+
+/// 请求和响应内容捕获，在后台线程重组、脱敏并写入按大小轮转的文件
+pub struct CaptureLogger {
+    sender: mpsc::Sender<CaptureJob>,
+}
+
+impl CaptureLogger {
+    pub fn new(config: &CaptureConfig) -> Self {
+        let mut writer = rotating_file(&config.file, config.max_files, config.max_file_size);
+
+        let (sender, receiver) = mpsc::channel::<CaptureJob>();
+        thread::spawn(move || {
+            for job in receiver {
+                let record = job.finish();
+                let result = serde_json::to_string(&record)
+                    .map_err(std::io::Error::from)
+                    .and_then(|line| writeln!(writer, "{}", line));
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to write capture record for request {}: {}",
+                        record.request_id, e
+                    );
+                }
+            }
+        });
+
+        Self { sender }
+    }
+}
+
+impl CaptureJob {
+    /// 解析响应体并按规则脱敏
+    fn finish(self) -> CaptureRecord {
+        let mut record = self.record;
+        record.response = match self.body {
+            CapturedBody::Json(body) => serde_json::from_slice(&body)
+                .unwrap_or_else(|_| json!(String::from_utf8_lossy(&body))),
+            CapturedBody::Sse(body) => reassemble_sse(&body),
+            CapturedBody::AnthropicSse(body) => reassemble_anthropic_sse(&body),
+            CapturedBody::Truncated(body) => {
+                record.truncated = true;
+                json!(String::from_utf8_lossy(&body))
+            }
+            CapturedBody::Value(value) => value,
+        };
+        for path in &self.redact {
+            let segments: Vec<&str> = path.split('.').collect();
+            redact(&mut record.request, &segments);
+            redact(&mut record.response, &segments);
+        }
+        record
+    }
+}
+
+/// 一次请求的捕获，请求结束后写入
+pub struct Capture {
+    logger: Arc<CaptureLogger>,
+    record: CaptureRecord,
+    redact: Vec<String>,
+    max_body_bytes: usize,
+}
+
+impl Capture {
+    /// 客户端或模型别名开启了捕获时开始捕获
+    pub fn start(
+        logger: Option<&Arc<CaptureLogger>>,
+        config: Option<&CaptureConfig>,
+        request_id: &str,
+        client: &str,
+        alias: &str,
+        request: &Value,
+    ) -> Option<Self> {
+        let (logger, config) = (logger?, config?);
+        let enabled = config.clients.iter().any(|name| name == client)
+            || config
+                .aliases
+                .iter()
+                .any(|pattern| glob_match(pattern, alias));
+        if !enabled {
+            return None;
+        }
+
+        Some(Self {
+            logger: logger.clone(),
+            record: CaptureRecord {
+                timestamp: Utc::now(),
+                request_id: request_id.to_string(),
+                client: client.to_string(),
+                alias: alias.to_string(),
+                provider: None,
+                model: None,
+                status: 0,
+                request: request.clone(),
+                response: Value::Null,
+                truncated: false,
+            },
+            redact: config.redact.clone(),
+            max_body_bytes: config.max_body_bytes.unwrap_or(1024 * 1024),
+        })
+    }
+
+    /// 记录上游的处理结果
+    pub fn set_upstream(&mut self, provider: &str, model: &str, status: u16) {
+        self.record.provider = Some(provider.to_string());
+        self.record.model = Some(model.to_string());
+        self.record.status = status;
+    }
+
+    /// 请求失败时记录返回给客户端的错误
+    pub fn finish_error(mut self, status: u16, error: Value) {
+        self.record.status = status;
+        self.send(CapturedBody::Value(error));
+    }
+
+    /// 复制经过的响应体，数据块原样立即返回，响应体传输结束后再写入
+    pub fn tee(self, body: ByteStream, endpoint_type: EndpointType, stream: bool) -> ByteStream {
+        let mut tap = CaptureTap {
+            capture: Some(self),
+            buffer: Vec::new(),
+            truncated: false,
+            stream,
+            anthropic: matches!(endpoint_type, EndpointType::Messages),
+        };
+        body.map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                tap.push(bytes);
+            }
+            chunk
+        })
+        .boxed()
+    }
+
+    fn send(self, body: CapturedBody) {
+        let _ = self.logger.sender.send(CaptureJob {
+            record: self.record,
+            body,
+            redact: self.redact,
+        });
+    }
+}
+
+/// 缓存经过的响应体，超过上限后只保留前 `max_body_bytes` 字节
+struct CaptureTap {
+    capture: Option<Capture>,
+    buffer: Vec<u8>,
+    truncated: bool,
+    stream: bool,
+    /// 响应是否为 Anthropic 格式
+    anthropic: bool,
+}
+
+impl CaptureTap {
+    fn push(&mut self, bytes: &Bytes) {
+        let Some(capture) = &self.capture else {
+            return;
+        };
+        let room = capture.max_body_bytes.saturating_sub(self.buffer.len());
+        if bytes.len() > room {
+            self.truncated = true;
+        }
+        self.buffer
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+impl Drop for CaptureTap {
+    fn drop(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            let buffer = std::mem::take(&mut self.buffer);
+            let body = if self.truncated {
+                CapturedBody::Truncated(buffer)
+            } else if self.stream && self.anthropic {
+                CapturedBody::AnthropicSse(buffer)
+            } else if self.stream {
+                CapturedBody::Sse(buffer)
+            } else {
+                CapturedBody::Json(buffer)
+            };
+            capture.send(body);
+        }
+    }
+}
+
+/// 流式响应中单个 choice 的累计内容
+#[derive(Default)]
+struct ChoiceState {
+    role: Option<Value>,
+    content: String,
+    reasoning_content: String,
+    /// 按 index 累计的工具调用，参数分多个数据块返回
+    tool_calls: BTreeMap<u64, Value>,
+    finish_reason: Value,
+}
+
+/// 将 OpenAI 格式的流式响应重组为完整的 `chat.completion` 对象
+fn reassemble_sse(body: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(body);
+    let mut head = Map::new();
+    let mut choices: BTreeMap<u64, ChoiceState> = BTreeMap::new();
+    let mut usage = Value::Null;
+
+    for line in text.lines() {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        for field in ["id", "model", "created", "system_fingerprint"] {
+            if !chunk[field].is_null() && !head.contains_key(field) {
+                head.insert(field.to_string(), chunk[field].clone());
+            }
+        }
+        if chunk["usage"].is_object() {
+            usage = chunk["usage"].clone();
+        }
+
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let state = choices
+                .entry(choice["index"].as_u64().unwrap_or(0))
+                .or_default();
+            let delta = &choice["delta"];
+            if !delta["role"].is_null() {
+                state.role = Some(delta["role"].clone());
+            }
+            if let Some(content) = delta["content"].as_str() {
+                state.content.push_str(content);
+            }
+            if let Some(reasoning) = delta["reasoning_content"].as_str() {
+                state.reasoning_content.push_str(reasoning);
+            }
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                let entry = state
+                    .tool_calls
+                    .entry(call["index"].as_u64().unwrap_or(0))
+                    .or_insert_with(|| {
+                        json!({"id": null, "type": "function", "function": {"name": "", "arguments": ""}})
+                    });
+                if !call["id"].is_null() {
+                    entry["id"] = call["id"].clone();
+                }
+                for field in ["name", "arguments"] {
+                    if let Some(part) = call["function"][field].as_str() {
+                        let merged = format!(
+                            "{}{}",
+                            entry["function"][field].as_str().unwrap_or_default(),
+                            part
+                        );
+                        entry["function"][field] = json!(merged);
+                    }
+                }
+            }
+            if !choice["finish_reason"].is_null() {
+                state.finish_reason = choice["finish_reason"].clone();
+            }
+        }
+    }
+
+    let choices: Vec<Value> = choices
+        .into_iter()
+        .map(|(index, state)| {
+            let mut message = json!({
+                "role": state.role.unwrap_or_else(|| json!("assistant")),
+                "content": state.content,
+            });
+            if !state.reasoning_content.is_empty() {
+                message["reasoning_content"] = json!(state.reasoning_content);
+            }
+            if !state.tool_calls.is_empty() {
+                message["tool_calls"] = json!(state.tool_calls.into_values().collect::<Vec<_>>());
+            }
+            json!({
+                "index": index,
+                "message": message,
+                "finish_reason": state.finish_reason,
+            })
+        })
+        .collect();
+
+    let mut response = head;
+    response.insert("object".to_string(), json!("chat.completion"));
+    response.insert("choices".to_string(), json!(choices));
+    response.insert("usage".to_string(), usage);
+    Value::Object(response)
+}
+
+/// 将 Anthropic 格式的流式响应重组为完整的 message 对象
+fn reassemble_anthropic_sse(body: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(body);
+    let mut message = json!({});
+    let mut blocks: BTreeMap<u64, Value> = BTreeMap::new();
+    // 工具调用的参数以 JSON 片段返回，全部收到后再解析
+    let mut partial_json: BTreeMap<u64, String> = BTreeMap::new();
+
+    for line in text.lines() {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        let index = event["index"].as_u64().unwrap_or(0);
+        match event["type"].as_str() {
+            Some("message_start") => message = event["message"].clone(),
+            Some("content_block_start") => {
+                blocks.insert(index, event["content_block"].clone());
+            }
+            Some("content_block_delta") => {
+                let delta = &event["delta"];
+                if let Some(part) = delta["partial_json"].as_str() {
+                    partial_json.entry(index).or_default().push_str(part);
+                    continue;
+                }
+                let Some(block) = blocks.get_mut(&index) else {
+                    continue;
+                };
+                for field in ["text", "thinking", "signature"] {
+                    if let Some(part) = delta[field].as_str() {
+                        let merged =
+                            format!("{}{}", block[field].as_str().unwrap_or_default(), part);
+                        block[field] = json!(merged);
+                    }
+                }
+            }
+            Some("message_delta") => {
+                for (key, value) in event["delta"].as_object().into_iter().flatten() {
+                    message[key] = value.clone();
+                }
+                for (key, value) in event["usage"].as_object().into_iter().flatten() {
+                    message["usage"][key] = value.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (index, arguments) in partial_json {
+        if let Some(block) = blocks.get_mut(&index) {
+            block["input"] = serde_json::from_str(&arguments).unwrap_or(json!(arguments));
+        }
+    }
+    message["content"] = json!(blocks.into_values().collect::<Vec<_>>());
+    message
+}
+
+/// 将路径匹配的字段替换为 `[REDACTED]`，`*` 匹配任意字段或数组元素
+fn redact(value: &mut Value, path: &[&str]) {
+    let Some((segment, rest)) = path.split_first() else {
+        return;
+    };
+    let children: Vec<&mut Value> = match value {
+        Value::Object(map) if *segment == "*" => map.values_mut().collect(),
+        Value::Object(map) => map.get_mut(*segment).into_iter().collect(),
+        Value::Array(items) if *segment == "*" => items.iter_mut().collect(),
+        Value::Array(items) => segment
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+    for child in children {
+        if rest.is_empty() {
+            *child = json!(REDACTED);
+        } else {
+            redact(child, rest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(mut value: Value, path: &str) -> Value {
+        let segments: Vec<&str> = path.split('.').collect();
+        redact(&mut value, &segments);
+        value
+    }
+
+    #[test]
+    fn redact_replaces_matching_fields() {
+        let value = json!({"user": "alice", "metadata": {"user_id": "u1", "keep": 1}});
+        assert_eq!(
+            redacted(value.clone(), "metadata.user_id"),
+            json!({"user": "alice", "metadata": {"user_id": REDACTED, "keep": 1}})
+        );
+        // 不存在的路径保持不变
+        assert_eq!(redacted(value.clone(), "metadata.missing.field"), value);
+    }
+
+    #[test]
+    fn redact_supports_wildcards_and_indexes() {
+        let value = json!({"messages": [
+            {"role": "system", "content": "secret"},
+            {"role": "user", "content": "hi"}
+        ]});
+        assert_eq!(
+            redacted(value.clone(), "messages.*.content"),
+            json!({"messages": [
+                {"role": "system", "content": REDACTED},
+                {"role": "user", "content": REDACTED}
+            ]})
+        );
+        assert_eq!(
+            redacted(value, "messages.0.content"),
+            json!({"messages": [
+                {"role": "system", "content": REDACTED},
+                {"role": "user", "content": "hi"}
+            ]})
+        );
+    }
+
+    #[test]
+    fn reassemble_sse_merges_content_and_tool_calls() {
+        let chunks = [
+            json!({"id": "c1", "model": "gpt-4", "created": 1, "choices": [{"index": 0, "delta": {"role": "assistant", "content": "Hel"}}]}),
+            json!({"id": "c1", "choices": [{"index": 0, "delta": {"content": "lo", "tool_calls": [
+                {"index": 0, "id": "call_1", "function": {"name": "get_", "arguments": "{\"a\""}}
+            ]}}]}),
+            json!({"id": "c1", "choices": [{"index": 0, "delta": {"tool_calls": [
+                {"index": 0, "function": {"name": "weather", "arguments": ":1}"}}
+            ]}, "finish_reason": "tool_calls"}]}),
+            json!({"id": "c1", "choices": [], "usage": {"prompt_tokens": 5, "completion_tokens": 2}}),
+        ];
+        let mut body = String::new();
+        for chunk in &chunks {
+            body.push_str(&format!("data: {}\n\n", chunk));
+        }
+        body.push_str("data: [DONE]\n\n");
+
+        let response = reassemble_sse(body.as_bytes());
+        assert_eq!(response["object"], "chat.completion");
+        assert_eq!(response["id"], "c1");
+        assert_eq!(response["model"], "gpt-4");
+        let choice = &response["choices"][0];
+        assert_eq!(choice["message"]["role"], "assistant");
+        assert_eq!(choice["message"]["content"], "Hello");
+        assert_eq!(
+            choice["message"]["tool_calls"][0],
+            json!({"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"a\":1}"}})
+        );
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(response["usage"]["prompt_tokens"], 5);
+    }
+
+    #[test]
+    fn reassemble_anthropic_sse_merges_blocks() {
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1", "type": "message", "role": "assistant", "model": "claude-x", "content": [], "stop_reason": null, "usage": {"input_tokens": 10, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "Let me "}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "check"}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "signature_delta", "signature": "sig"}}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "tu_1", "name": "get_weather", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"city\":"}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"Paris\"}"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "stop_sequence", "stop_sequence": "END"}, "usage": {"output_tokens": 7}}),
+            json!({"type": "message_stop"}),
+        ];
+        let mut body = String::new();
+        for event in &events {
+            body.push_str(&format!(
+                "event: {}\ndata: {}\n\n",
+                event["type"].as_str().unwrap(),
+                event
+            ));
+        }
+
+        let message = reassemble_anthropic_sse(body.as_bytes());
+        assert_eq!(message["id"], "msg_1");
+        assert_eq!(
+            message["content"],
+            json!([
+                {"type": "thinking", "thinking": "Let me check", "signature": "sig"},
+                {"type": "tool_use", "id": "tu_1", "name": "get_weather", "input": {"city": "Paris"}}
+            ])
+        );
+        assert_eq!(message["stop_reason"], "stop_sequence");
+        assert_eq!(message["stop_sequence"], "END");
+        assert_eq!(
+            message["usage"],
+            json!({"input_tokens": 10, "output_tokens": 7})
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use axum::http::{header, HeaderMap, HeaderName};
+
+use crate::config::{HeaderForwardConfig, ResponseHeadersConfig};
+
+/// 标明实际处理请求的提供者
+pub const PROVIDER_HEADER: &str = "x-ai-forward-provider";
+/// 本次转发的请求 ID
+pub const REQUEST_ID_HEADER: &str = "x-ai-forward-request-id";
+
+/// 逐跳头部，只对单个连接有效，不能转发
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::PROXY_AUTHENTICATE,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// 无论配置如何都不会转发给上游的客户端请求头：客户端自己的凭证，以及由代理重新生成的请求头
+const ALWAYS_STRIPPED_REQUEST_HEADERS: [HeaderName; 9] = [
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    HeaderName::from_static("x-api-key"),
+    HeaderName::from_static("api-key"),
+    header::HOST,
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::ACCEPT_ENCODING,
+    header::COOKIE,
+];
+
+/// 判断是否为逐跳头部，包括 `Connection` 中列出的头部
+fn is_hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    HOP_BY_HOP_HEADERS.contains(name)
+        || headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(name.as_str()))
+}
+
+/// 按提供者配置筛选需要透传给上游的客户端请求头
+///
+/// 未配置时不透传任何请求头；`allow` 中的 `*` 表示允许所有请求头，`deny` 优先于 `allow`
+pub fn forwardable_request_headers(
+    client_headers: &HeaderMap,
+    config: Option<&HeaderForwardConfig>,
+) -> HeaderMap {
+    let mut forwarded = HeaderMap::new();
+    let Some(config) = config else {
+        return forwarded;
+    };
+
+    let matches = |list: &[String], name: &HeaderName| {
+        list.iter()
+            .any(|entry| entry == "*" || entry.eq_ignore_ascii_case(name.as_str()))
+    };
+
+    for (name, value) in client_headers {
+        if ALWAYS_STRIPPED_REQUEST_HEADERS.contains(name) || is_hop_by_hop(name, client_headers) {
+            continue;
+        }
+        if matches(&config.allow, name) && !matches(&config.deny, name) {
+            forwarded.append(name.clone(), value.clone());
+        }
+    }
+
+    forwarded
+}
+
+/// 无论配置如何都不会返回给客户端的上游响应头，响应体由代理重新分帧
+const ALWAYS_STRIPPED_RESPONSE_HEADERS: [HeaderName; 3] = [
+    header::CONTENT_LENGTH,
+    header::SET_COOKIE,
+    HeaderName::from_static("set-cookie2"),
+];
+
+/// 能识别上游厂商或账号的响应头前缀
+const VENDOR_HEADER_PREFIXES: [&str; 7] = [
+    "openai-",
+    "anthropic-",
+    "x-ratelimit-",
+    "x-ms-",
+    "x-goog-",
+    "cf-",
+    "x-envoy-",
+];
+
+/// 能识别上游厂商或账号的响应头
+const VENDOR_HEADERS: [&str; 8] = [
+    "server",
+    "via",
+    "alt-svc",
+    "request-id",
+    "x-request-id",
+    "apim-request-id",
+    "azureml-model-session",
+    "server-timing",
+];
+
+fn is_vendor_header(name: &HeaderName) -> bool {
+    let name = name.as_str();
+    VENDOR_HEADERS.contains(&name)
+        || VENDOR_HEADER_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// 按响应头策略筛选返回给客户端的上游响应头
+pub fn sanitize_response_headers(
+    upstream: &HeaderMap,
+    config: Option<&ResponseHeadersConfig>,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in upstream {
+        if ALWAYS_STRIPPED_RESPONSE_HEADERS.contains(name) || is_hop_by_hop(name, upstream) {
+            continue;
+        }
+        if let Some(config) = config {
+            if config.hide_vendor_headers && is_vendor_header(name) {
+                continue;
+            }
+            if config
+                .hide
+                .iter()
+                .any(|hidden| hidden.eq_ignore_ascii_case(name.as_str()))
+            {
+                continue;
+            }
+        }
+        headers.append(name.clone(), value.clone());
+    }
+    headers
+}
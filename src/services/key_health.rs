@@ -0,0 +1,374 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::time::{Duration, Instant};
+
+/// 上游没有给出重置时间时，限流密钥的默认冷却时间
+const DEFAULT_RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+/// 上游返回 403 时密钥的隔离时间
+const FORBIDDEN_QUARANTINE: Duration = Duration::from_secs(300);
+
+/// 单个密钥的健康状态
+#[derive(Debug, Clone)]
+pub enum KeyHealth {
+    Healthy,
+    /// 被限流，冷却到指定时间后恢复
+    RateLimited {
+        until: Instant,
+    },
+    /// 上游拒绝访问（403），隔离到指定时间后恢复
+    ///
+    /// 403 也可能是地区、模型权限或内容审核导致的，不能据此判定密钥失效
+    Quarantined {
+        until: Instant,
+        reason: String,
+    },
+    /// 被判定为永久失效（401 或 Gemini 的 `API_KEY_INVALID`），需要重新加载配置才能恢复
+    Disabled {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct KeyState {
+    health: KeyHealth,
+    /// 最近一次收到 429 的时间
+    last_rate_limited: Option<DateTime<Utc>>,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        Self {
+            health: KeyHealth::Healthy,
+            last_rate_limited: None,
+        }
+    }
+}
+
+/// 密钥健康状态管理器，根据上游响应隔离被限流或失效的密钥
+#[derive(Default)]
+pub struct KeyHealthManager {
+    states: DashMap<String, KeyState>,
+}
+
+impl KeyHealthManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 检查密钥当前是否可用，冷却期已过的限流密钥会自动恢复
+    pub fn is_available(&self, key: &str) -> bool {
+        let Some(mut state) = self.states.get_mut(key) else {
+            return true;
+        };
+
+        match state.health {
+            KeyHealth::Healthy => true,
+            KeyHealth::RateLimited { until } | KeyHealth::Quarantined { until, .. } => {
+                if Instant::now() >= until {
+                    state.health = KeyHealth::Healthy;
+                    true
+                } else {
+                    false
+                }
+            }
+            KeyHealth::Disabled { .. } => false,
+        }
+    }
+
+    /// 获取密钥的健康状态
+    pub fn health(&self, key: &str) -> KeyHealth {
+        self.states
+            .get(key)
+            .map(|s| s.health.clone())
+            .unwrap_or(KeyHealth::Healthy)
+    }
+
+    /// 获取密钥最近一次被限流的时间
+    pub fn last_rate_limited(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.states.get(key).and_then(|s| s.last_rate_limited)
+    }
+
+    /// 请求成功，恢复密钥为健康状态
+    pub fn record_success(&self, key: &str) {
+        if let Some(mut state) = self.states.get_mut(key) {
+            if !matches!(state.health, KeyHealth::Disabled { .. }) {
+                state.health = KeyHealth::Healthy;
+            }
+        }
+    }
+
+    /// 根据上游失败响应的状态码、响应头和响应体更新密钥状态
+    pub fn record_failure(&self, key: &str, status: StatusCode, headers: &HeaderMap, body: &str) {
+        match status.as_u16() {
+            429 => {
+                let cooldown = rate_limit_cooldown(headers).unwrap_or(DEFAULT_RATE_LIMIT_COOLDOWN);
+                let mut state = self.states.entry(key.to_string()).or_default();
+                state.health = KeyHealth::RateLimited {
+                    until: Instant::now() + cooldown,
+                };
+                state.last_rate_limited = Some(Utc::now());
+                tracing::warn!(
+                    "API key {} rate limited, cooling down for {:?}",
+                    mask_key(key),
+                    cooldown
+                );
+            }
+            401 => self.disable(key, format!("upstream returned {}", status)),
+            400 if is_invalid_key_error(body) => {
+                self.disable(key, "upstream reported an invalid API key".to_string())
+            }
+            403 => {
+                let mut state = self.states.entry(key.to_string()).or_default();
+                state.health = KeyHealth::Quarantined {
+                    until: Instant::now() + FORBIDDEN_QUARANTINE,
+                    reason: format!("upstream returned {}", status),
+                };
+                tracing::warn!(
+                    "API key {} quarantined for {:?} after upstream returned {}",
+                    mask_key(key),
+                    FORBIDDEN_QUARANTINE,
+                    status
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn disable(&self, key: &str, reason: String) {
+        tracing::warn!("API key {} disabled: {}", mask_key(key), reason);
+        let mut state = self.states.entry(key.to_string()).or_default();
+        state.health = KeyHealth::Disabled { reason };
+    }
+
+    /// 清空所有密钥的健康状态
+    pub fn reset(&self) {
+        self.states.clear();
+    }
+}
+
+/// 上游是否报告密钥无效，Gemini 对无效密钥返回 400，并在 `details` 中给出 `API_KEY_INVALID`
+pub fn is_invalid_key_error(body: &str) -> bool {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return false;
+    };
+    // Gemini 部分接口以数组形式返回错误
+    let error = match &value {
+        serde_json::Value::Array(items) => &items.first().unwrap_or(&value)["error"],
+        value => &value["error"],
+    };
+    error["details"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|detail| detail["reason"] == "API_KEY_INVALID")
+}
+
+/// 解析上游的 `Retry-After` 响应头，支持秒数和 HTTP 日期
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_reset_value)
+}
+
+/// 从响应头中计算冷却时间，优先使用 `Retry-After`
+///
+/// 其次使用已用尽的限额（`x-ratelimit-remaining-*` 为 0）对应的 `x-ratelimit-reset-*`，
+/// 无法判断哪个限额用尽时取最短的重置时间，避免按未用尽的日限额等长时间冷却
+fn rate_limit_cooldown(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(retry_after) = retry_after(headers) {
+        return Some(retry_after);
+    }
+
+    let resets: Vec<(&str, Duration)> = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let limit = name.as_str().strip_prefix("x-ratelimit-reset-")?;
+            Some((limit, parse_reset_value(value.to_str().ok()?)?))
+        })
+        .collect();
+    let exhausted = |limit: &str| {
+        headers
+            .get(format!("x-ratelimit-remaining-{}", limit))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .is_some_and(|remaining| remaining <= 0.0)
+    };
+
+    resets
+        .iter()
+        .filter(|(limit, _)| exhausted(limit))
+        .map(|(_, reset)| *reset)
+        .max()
+        .or_else(|| resets.iter().map(|(_, reset)| *reset).min())
+}
+
+/// 解析重置时间，支持秒数、`6m0s` 形式的时长、HTTP 日期和 RFC 3339 时间
+fn parse_reset_value(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = value.parse::<f64>() {
+        return (secs >= 0.0).then(|| Duration::from_secs_f64(secs));
+    }
+
+    if let Some(duration) = parse_go_duration(value) {
+        return Some(duration);
+    }
+
+    let reset_at = DateTime::parse_from_rfc2822(value)
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .ok()?;
+    (reset_at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+/// 解析 `1h2m3.5s`、`20ms` 形式的时长
+fn parse_go_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|&i| i > 0)?;
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * multiplier;
+        rest = &rest[unit_end..];
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
+
+/// 隐藏密钥中间部分，只用于日志和统计展示
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let prefix: String = chars[..4].iter().collect();
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", prefix, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parse_reset_value_formats() {
+        assert_eq!(parse_reset_value("20"), Some(Duration::from_secs(20)));
+        assert_eq!(parse_reset_value("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset_value("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset_value("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset_value("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset_value("-1"), None);
+        assert_eq!(parse_reset_value("soon"), None);
+    }
+
+    #[test]
+    fn cooldown_prefers_retry_after() {
+        let headers = headers(&[
+            ("retry-after", "5"),
+            ("x-ratelimit-remaining-tokens", "0"),
+            ("x-ratelimit-reset-tokens", "1m0s"),
+        ]);
+        assert_eq!(rate_limit_cooldown(&headers), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn cooldown_uses_exhausted_limit_reset() {
+        let headers = headers(&[
+            ("x-ratelimit-remaining-requests", "0"),
+            ("x-ratelimit-reset-requests", "2s"),
+            ("x-ratelimit-remaining-tokens", "5000"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ]);
+        assert_eq!(rate_limit_cooldown(&headers), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn cooldown_without_remaining_uses_shortest_reset() {
+        let headers = headers(&[
+            ("x-ratelimit-reset-requests", "3s"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ]);
+        assert_eq!(rate_limit_cooldown(&headers), Some(Duration::from_secs(3)));
+        assert_eq!(rate_limit_cooldown(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn mask_key_hides_middle() {
+        assert_eq!(mask_key("sk-1234567890"), "sk-1...7890");
+        assert_eq!(mask_key("short"), "*****");
+    }
+
+    #[test]
+    fn forbidden_quarantines_key_until_success() {
+        let manager = KeyHealthManager::new();
+        manager.record_failure("k1", StatusCode::FORBIDDEN, &HeaderMap::new(), "");
+        assert!(!manager.is_available("k1"));
+        assert!(matches!(
+            manager.health("k1"),
+            KeyHealth::Quarantined { until, .. } if until > Instant::now() + Duration::from_secs(60)
+        ));
+
+        manager.record_success("k1");
+        assert!(manager.is_available("k1"));
+    }
+
+    #[test]
+    fn unauthorized_and_invalid_gemini_key_disable_key() {
+        let manager = KeyHealthManager::new();
+        manager.record_failure("k1", StatusCode::UNAUTHORIZED, &HeaderMap::new(), "");
+        manager.record_success("k1");
+        assert!(!manager.is_available("k1"));
+
+        let gemini = r#"{"error": {"code": 400, "message": "API key not valid.", "status": "INVALID_ARGUMENT",
+            "details": [{"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "API_KEY_INVALID"}]}}"#;
+        manager.record_failure("k2", StatusCode::BAD_REQUEST, &HeaderMap::new(), gemini);
+        assert!(matches!(manager.health("k2"), KeyHealth::Disabled { .. }));
+
+        // 普通的 400 是请求本身的问题，不影响密钥
+        let bad_request = r#"{"error": {"code": 400, "message": "Invalid JSON payload"}}"#;
+        manager.record_failure(
+            "k3",
+            StatusCode::BAD_REQUEST,
+            &HeaderMap::new(),
+            bad_request,
+        );
+        assert!(manager.is_available("k3"));
+    }
+
+    #[test]
+    fn invalid_key_error_in_array_form() {
+        assert!(is_invalid_key_error(
+            r#"[{"error": {"details": [{"reason": "API_KEY_INVALID"}]}}]"#
+        ));
+        assert!(!is_invalid_key_error("API_KEY_INVALID"));
+        assert!(!is_invalid_key_error("[]"));
+    }
+}
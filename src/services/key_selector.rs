@@ -0,0 +1,258 @@
+use dashmap::DashMap;
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::{KeySelectionStrategy, Provider};
+use crate::services::key_health::KeyHealthManager;
+
+/// 选择密钥时可用的上下文信息
+pub struct SelectionContext<'a> {
+    pub provider: &'a Provider,
+    /// 可以选择的密钥，已排除本次请求失败过的和处于隔离状态的密钥，保持配置中的顺序
+    pub candidates: Vec<&'a String>,
+    pub key_usage: &'a DashMap<String, u64>,
+    pub key_health: &'a KeyHealthManager,
+    /// 客户端令牌，用于粘性选择
+    pub client_token: Option<&'a str>,
+}
+
+impl<'a> SelectionContext<'a> {
+    fn usage(&self, key: &str) -> u64 {
+        self.key_usage.get(key).map(|v| *v).unwrap_or(0)
+    }
+
+    fn weight(&self, key: &str) -> u32 {
+        let weights = self.provider.key_weights.as_deref().unwrap_or_default();
+        self.provider
+            .keys
+            .iter()
+            .position(|k| k == key)
+            .and_then(|i| weights.get(i).copied())
+            .unwrap_or(1)
+    }
+
+    /// 从配置中第 `start` 个密钥开始循环查找第一个可选的密钥
+    fn first_candidate_from(&self, start: usize) -> Option<&'a String> {
+        let keys = &self.provider.keys;
+        (0..keys.len())
+            .map(|offset| &keys[(start + offset) % keys.len()])
+            .find(|key| self.candidates.contains(key))
+    }
+}
+
+/// 密钥选择策略
+pub trait KeySelector: Send + Sync {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String>;
+}
+
+/// 按配置顺序轮询
+#[derive(Default)]
+pub struct RoundRobinSelector {
+    counters: DashMap<String, AtomicUsize>,
+}
+
+impl KeySelector for RoundRobinSelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        let counter = self
+            .counters
+            .entry(ctx.provider.name.clone())
+            .or_insert_with(|| AtomicUsize::new(0));
+        let start = counter.fetch_add(1, Ordering::Relaxed);
+        ctx.first_candidate_from(start)
+    }
+}
+
+/// 选择累计使用次数最少的密钥
+pub struct LeastUsedSelector;
+
+impl KeySelector for LeastUsedSelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        ctx.candidates
+            .iter()
+            .copied()
+            .min_by_key(|key| ctx.usage(key))
+    }
+}
+
+/// 随机选择
+pub struct RandomSelector;
+
+impl KeySelector for RandomSelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        if ctx.candidates.is_empty() {
+            return None;
+        }
+        let index = rand::rng().random_range(0..ctx.candidates.len());
+        Some(ctx.candidates[index])
+    }
+}
+
+/// 按 `key_weights` 加权随机选择
+pub struct WeightedSelector;
+
+impl KeySelector for WeightedSelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        let total: u64 = ctx.candidates.iter().map(|k| ctx.weight(k) as u64).sum();
+        if total == 0 {
+            return RandomSelector.select(ctx);
+        }
+
+        let mut point = rand::rng().random_range(0..total);
+        ctx.candidates.iter().copied().find(|key| {
+            let weight = ctx.weight(key) as u64;
+            if point < weight {
+                true
+            } else {
+                point -= weight;
+                false
+            }
+        })
+    }
+}
+
+/// 同一客户端令牌固定使用同一个密钥，该密钥不可用时顺延到下一个
+pub struct StickySelector;
+
+impl KeySelector for StickySelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        let Some(token) = ctx.client_token else {
+            return LeastUsedSelector.select(ctx);
+        };
+
+        let mut hasher = DefaultHasher::new();
+        token.hash(&mut hasher);
+        let start = (hasher.finish() % ctx.provider.keys.len().max(1) as u64) as usize;
+        ctx.first_candidate_from(start)
+    }
+}
+
+/// 优先选择最久没有被限流的密钥，从未被限流的密钥最优先，相同时选择使用次数少的
+pub struct LeastRecentRateLimitedSelector;
+
+impl KeySelector for LeastRecentRateLimitedSelector {
+    fn select<'a>(&self, ctx: &SelectionContext<'a>) -> Option<&'a String> {
+        ctx.candidates
+            .iter()
+            .copied()
+            .min_by_key(|key| (ctx.key_health.last_rate_limited(key), ctx.usage(key)))
+    }
+}
+
+/// 所有内置的密钥选择策略实例，带状态的策略在各提供者之间共享
+#[derive(Default)]
+pub struct KeySelectors {
+    round_robin: RoundRobinSelector,
+}
+
+impl KeySelectors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, strategy: KeySelectionStrategy) -> &dyn KeySelector {
+        match strategy {
+            KeySelectionStrategy::RoundRobin => &self.round_robin,
+            KeySelectionStrategy::LeastUsed => &LeastUsedSelector,
+            KeySelectionStrategy::Random => &RandomSelector,
+            KeySelectionStrategy::Weighted => &WeightedSelector,
+            KeySelectionStrategy::Sticky => &StickySelector,
+            KeySelectionStrategy::LeastRecentRateLimited => &LeastRecentRateLimitedSelector,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    fn provider() -> Provider {
+        serde_json::from_value(json!({
+            "name": "openai",
+            "endpoints": {"completions": "https://api.openai.com/v1/chat/completions"},
+            "keys": ["k1", "k2", "k3"],
+            "key_weights": [0, 5, 0]
+        }))
+        .unwrap()
+    }
+
+    fn context<'a>(
+        provider: &'a Provider,
+        key_usage: &'a DashMap<String, u64>,
+        key_health: &'a KeyHealthManager,
+        client_token: Option<&'a str>,
+    ) -> SelectionContext<'a> {
+        SelectionContext {
+            provider,
+            candidates: provider.keys.iter().collect(),
+            key_usage,
+            key_health,
+            client_token,
+        }
+    }
+
+    #[test]
+    fn round_robin_skips_excluded_keys() {
+        let provider = provider();
+        let (usage, health) = (DashMap::new(), KeyHealthManager::new());
+        let mut ctx = context(&provider, &usage, &health, None);
+        ctx.candidates.retain(|key| *key != "k2");
+
+        let selector = RoundRobinSelector::default();
+        let picks: Vec<_> = (0..4).map(|_| selector.select(&ctx).unwrap()).collect();
+        assert_eq!(picks, ["k1", "k3", "k3", "k1"]);
+    }
+
+    #[test]
+    fn least_used_picks_lowest_usage() {
+        let provider = provider();
+        let (usage, health) = (DashMap::new(), KeyHealthManager::new());
+        usage.insert("k1".to_string(), 5);
+        usage.insert("k2".to_string(), 2);
+        usage.insert("k3".to_string(), 9);
+
+        let ctx = context(&provider, &usage, &health, None);
+        assert_eq!(LeastUsedSelector.select(&ctx).unwrap(), "k2");
+    }
+
+    #[test]
+    fn weighted_never_picks_zero_weight_keys() {
+        let provider = provider();
+        let (usage, health) = (DashMap::new(), KeyHealthManager::new());
+        let ctx = context(&provider, &usage, &health, None);
+        for _ in 0..50 {
+            assert_eq!(WeightedSelector.select(&ctx).unwrap(), "k2");
+        }
+    }
+
+    #[test]
+    fn sticky_keeps_key_per_token_and_moves_on_when_excluded() {
+        let provider = provider();
+        let (usage, health) = (DashMap::new(), KeyHealthManager::new());
+        let mut ctx = context(&provider, &usage, &health, Some("client-token"));
+
+        let first = StickySelector.select(&ctx).unwrap();
+        assert_eq!(StickySelector.select(&ctx).unwrap(), first);
+
+        let position = provider.keys.iter().position(|k| k == first).unwrap();
+        let next = &provider.keys[(position + 1) % provider.keys.len()];
+        ctx.candidates.retain(|key| *key != first);
+        assert_eq!(StickySelector.select(&ctx).unwrap(), next);
+    }
+
+    #[test]
+    fn least_recent_rate_limited_prefers_never_limited_keys() {
+        let provider = provider();
+        let (usage, health) = (DashMap::new(), KeyHealthManager::new());
+        health.record_failure("k1", StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), "");
+        usage.insert("k2".to_string(), 3);
+        usage.insert("k3".to_string(), 1);
+
+        let ctx = context(&provider, &usage, &health, None);
+        assert_eq!(LeastRecentRateLimitedSelector.select(&ctx).unwrap(), "k3");
+    }
+}
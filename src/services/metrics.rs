@@ -0,0 +1,342 @@
+use dashmap::DashMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::services::usage::TokenUsage;
+
+/// 延迟直方图的桶上限，单位为秒
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// 固定分桶的直方图，每个桶只记录落在该区间内的样本数，输出时再累加
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Prometheus 文本格式的输出
+#[derive(Default)]
+pub struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输出指标的 HELP 和 TYPE 行，同一指标的样本需要紧跟在后面
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let le = bound.to_string();
+            let mut labels = labels.to_vec();
+            labels.push(("le", &le));
+            self.sample(&bucket, &labels, cumulative);
+        }
+        let mut labels_inf = labels.to_vec();
+        labels_inf.push(("le", "+Inf"));
+        self.sample(&bucket, &labels_inf, histogram.count);
+        self.sample(&format!("{}_sum", name), labels, histogram.sum);
+        self.sample(&format!("{}_count", name), labels, histogram.count);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// 转义标签值中的反斜杠、双引号和换行
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 请求相关的计数器和直方图，其他状态在输出时从各个管理器读取
+///
+/// 计数器只增不减，`/reset` 不会清零
+#[derive(Default)]
+pub struct Metrics {
+    /// 按 (模型别名, 提供者, 状态码) 统计的请求数，请求没有到达上游时提供者为空
+    ///
+    /// 模型别名由调用方限制为配置中的别名，未知模型统一记为 `unknown`
+    requests: DashMap<(String, String, u16), u64>,
+    /// 按提供者统计的上游响应头返回耗时
+    upstream_latency: DashMap<String, Histogram>,
+    /// 按提供者统计的从收到请求到返回第一个数据块的耗时
+    first_byte: DashMap<String, Histogram>,
+    /// 按 (模型别名, 提供者, 类型) 统计的 token 数
+    tokens: DashMap<(String, String, &'static str), u64>,
+    /// 按提供者统计的换用其他密钥重试的次数
+    retries: DashMap<String, u64>,
+    /// 按 (模型别名, 失败的提供者) 统计的回退到下一个提供者的次数
+    failovers: DashMap<(String, String), u64>,
+    /// 认证失败次数
+    auth_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_request(&self, alias: &str, provider: Option<&str>, status: u16) {
+        let key = (
+            alias.to_string(),
+            provider.unwrap_or_default().to_string(),
+            status,
+        );
+        *self.requests.entry(key).or_default() += 1;
+    }
+
+    pub fn observe_upstream_latency(&self, provider: &str, latency: Duration) {
+        self.upstream_latency
+            .entry(provider.to_string())
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn observe_first_byte(&self, provider: &str, latency: Duration) {
+        self.first_byte
+            .entry(provider.to_string())
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn record_tokens(&self, alias: &str, provider: &str, usage: &TokenUsage) {
+        for (kind, count) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+            ("cached", usage.cached_tokens),
+        ] {
+            *self
+                .tokens
+                .entry((alias.to_string(), provider.to_string(), kind))
+                .or_default() += count;
+        }
+    }
+
+    pub fn record_retry(&self, provider: &str) {
+        *self.retries.entry(provider.to_string()).or_default() += 1;
+    }
+
+    pub fn record_failover(&self, alias: &str, provider: &str) {
+        *self
+            .failovers
+            .entry((alias.to_string(), provider.to_string()))
+            .or_default() += 1;
+    }
+
+    pub fn record_auth_failure(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 输出所有计数器和直方图
+    pub fn render(&self, out: &mut Exposition) {
+        out.family(
+            "ai_forward_requests_total",
+            "counter",
+            "Proxied requests by model alias, provider and response status.",
+        );
+        for entry in self.requests.iter() {
+            let (alias, provider, status) = entry.key();
+            out.sample(
+                "ai_forward_requests_total",
+                &[
+                    ("alias", alias),
+                    ("provider", provider),
+                    ("status", &status.to_string()),
+                ],
+                *entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_upstream_latency_seconds",
+            "histogram",
+            "Time until the upstream returned response headers.",
+        );
+        for entry in self.upstream_latency.iter() {
+            out.histogram(
+                "ai_forward_upstream_latency_seconds",
+                &[("provider", entry.key())],
+                entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_time_to_first_byte_seconds",
+            "histogram",
+            "Time from receiving a request until the first response chunk was sent.",
+        );
+        for entry in self.first_byte.iter() {
+            out.histogram(
+                "ai_forward_time_to_first_byte_seconds",
+                &[("provider", entry.key())],
+                entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_tokens_total",
+            "counter",
+            "Tokens reported by upstream usage, by model alias, provider and type.",
+        );
+        for entry in self.tokens.iter() {
+            let (alias, provider, kind) = entry.key();
+            out.sample(
+                "ai_forward_tokens_total",
+                &[("alias", alias), ("provider", provider), ("type", kind)],
+                *entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_retries_total",
+            "counter",
+            "Retries with another API key of the same provider.",
+        );
+        for entry in self.retries.iter() {
+            out.sample(
+                "ai_forward_retries_total",
+                &[("provider", entry.key())],
+                *entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_failovers_total",
+            "counter",
+            "Fallbacks to the next provider after a provider failed.",
+        );
+        for entry in self.failovers.iter() {
+            let (alias, provider) = entry.key();
+            out.sample(
+                "ai_forward_failovers_total",
+                &[("alias", alias), ("provider", provider)],
+                *entry.value(),
+            );
+        }
+
+        out.family(
+            "ai_forward_auth_failures_total",
+            "counter",
+            "Requests rejected because of an invalid token.",
+        );
+        out.sample(
+            "ai_forward_auth_failures_total",
+            &[],
+            self.auth_failures.load(Ordering::Relaxed),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(metrics: &Metrics) -> String {
+        let mut out = Exposition::new();
+        metrics.render(&mut out);
+        out.finish()
+    }
+
+    #[test]
+    fn sample_escapes_label_values() {
+        let mut out = Exposition::new();
+        out.sample("m", &[("alias", "a\"b\\c\nd")], 1);
+        assert_eq!(out.finish(), "m{alias=\"a\\\"b\\\\c\\nd\"} 1\n");
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        metrics.observe_upstream_latency("openai", Duration::from_millis(80));
+        metrics.observe_upstream_latency("openai", Duration::from_millis(400));
+        metrics.observe_upstream_latency("openai", Duration::from_secs(600));
+
+        let text = render(&metrics);
+        let bucket = |le: &str| {
+            format!(
+                "ai_forward_upstream_latency_seconds_bucket{{provider=\"openai\",le=\"{}\"}}",
+                le
+            )
+        };
+        assert!(text.contains(&format!("{} 0\n", bucket("0.05"))));
+        assert!(text.contains(&format!("{} 1\n", bucket("0.1"))));
+        assert!(text.contains(&format!("{} 2\n", bucket("0.5"))));
+        assert!(text.contains(&format!("{} 2\n", bucket("300"))));
+        assert!(text.contains(&format!("{} 3\n", bucket("+Inf"))));
+        assert!(text.contains("ai_forward_upstream_latency_seconds_count{provider=\"openai\"} 3\n"));
+    }
+
+    #[test]
+    fn counters_accumulate_by_labels() {
+        let metrics = Metrics::new();
+        metrics.record_request("gpt-4", Some("openai"), 200);
+        metrics.record_request("gpt-4", Some("openai"), 200);
+        metrics.record_request("unknown", None, 404);
+        metrics.record_tokens(
+            "gpt-4",
+            "openai",
+            &TokenUsage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+                cached_tokens: 0,
+            },
+        );
+        metrics.record_retry("openai");
+        metrics.record_failover("gpt-4", "openai");
+        metrics.record_auth_failure();
+
+        let text = render(&metrics);
+        for line in [
+            "ai_forward_requests_total{alias=\"gpt-4\",provider=\"openai\",status=\"200\"} 2",
+            "ai_forward_requests_total{alias=\"unknown\",provider=\"\",status=\"404\"} 1",
+            "ai_forward_tokens_total{alias=\"gpt-4\",provider=\"openai\",type=\"prompt\"} 10",
+            "ai_forward_tokens_total{alias=\"gpt-4\",provider=\"openai\",type=\"completion\"} 5",
+            "ai_forward_retries_total{provider=\"openai\"} 1",
+            "ai_forward_failovers_total{alias=\"gpt-4\",provider=\"openai\"} 1",
+            "ai_forward_auth_failures_total 1",
+        ] {
+            assert!(text.contains(&format!("{}\n", line)), "missing: {}", line);
+        }
+        assert!(text.contains("# TYPE ai_forward_requests_total counter\n"));
+    }
+}
@@ -1 +1,15 @@
+pub mod acl;
+pub mod adapters;
+pub mod audit;
 pub mod ai;
+pub mod balancer;
+pub mod capture;
+pub mod headers;
+pub mod key_health;
+pub mod key_selector;
+pub mod metrics;
+pub mod persistence;
+pub mod quota;
+pub mod rate_limit;
+pub mod timeouts;
+pub mod usage;
@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::config::{PersistenceBackend, PersistenceConfig};
+use crate::services::quota::ClientUsage;
+use crate::services::usage::UsageRecords;
+
+/// JSON Lines 文件超过该行数后压缩为只保留最新快照
+const MAX_LINES: usize = 1000;
+
+/// 需要持久化的统计数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSnapshot {
+    /// 各提供者的请求次数
+    #[serde(default)]
+    pub provider_usage: BTreeMap<String, u64>,
+    /// 各密钥的使用次数，以密钥指纹为键，文件中不保存密钥原文
+    #[serde(default)]
+    pub key_usage: BTreeMap<String, u64>,
+    /// token 用量和费用
+    #[serde(default)]
+    pub token_usage: UsageRecords,
+    /// 各客户端的配额用量
+    #[serde(default)]
+    pub quotas: BTreeMap<String, ClientUsage>,
+}
+
+/// 密钥的稳定指纹（FNV-1a 64 位），用于在持久化数据中标识密钥
+pub fn key_fingerprint(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// 统计数据存储后端
+pub trait UsageStore: Send + Sync {
+    /// 读取最近一次保存的快照，没有数据时返回 `None`
+    fn load(&self) -> io::Result<Option<UsageSnapshot>>;
+
+    /// 保存快照
+    fn save(&self, snapshot: &UsageSnapshot) -> io::Result<()>;
+}
+
+/// 按配置创建存储后端
+pub fn open_store(config: &PersistenceConfig) -> Arc<dyn UsageStore> {
+    match config.backend {
+        PersistenceBackend::JsonLines => Arc::new(JsonLinesStore::new(&config.path)),
+    }
+}
+
+/// 追加写入的 JSON Lines 文件，每行是一份完整快照
+pub struct JsonLinesStore {
+    path: PathBuf,
+    state: Mutex<JsonLinesState>,
+}
+
+#[derive(Default)]
+struct JsonLinesState {
+    /// 文件当前的行数
+    lines: usize,
+    /// 最后写入的一行，内容没有变化时不重复写入
+    last: Option<String>,
+}
+
+impl JsonLinesStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            state: Mutex::new(JsonLinesState::default()),
+        }
+    }
+
+    /// 用最新快照重写文件，先写临时文件再替换，避免写入中断导致数据丢失
+    fn compact(&self, line: &str) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            writeln!(file, "{}", line)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+impl UsageStore for JsonLinesStore {
+    fn load(&self) -> io::Result<Option<UsageSnapshot>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut latest = None;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            state.lines += 1;
+            // 进程在写入时退出可能留下不完整的最后一行，跳过后使用上一份快照
+            match serde_json::from_str::<UsageSnapshot>(&line) {
+                Ok(snapshot) => {
+                    latest = Some(snapshot);
+                    state.last = Some(line);
+                }
+                Err(e) => warn!(
+                    "Skipping invalid line {} in '{}': {}",
+                    state.lines,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(latest)
+    }
+
+    fn save(&self, snapshot: &UsageSnapshot) -> io::Result<()> {
+        let line = serde_json::to_string(snapshot)?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.last.as_deref() == Some(line.as_str()) {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        if state.lines >= MAX_LINES {
+            self.compact(&line)?;
+            state.lines = 1;
+        } else {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", line)?;
+            state.lines += 1;
+        }
+        state.last = Some(line);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个测试使用独立的临时文件
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ai_forward_persistence_{}_{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn snapshot(requests: u64) -> UsageSnapshot {
+        let mut snapshot = UsageSnapshot::default();
+        snapshot
+            .provider_usage
+            .insert("openai".to_string(), requests);
+        snapshot
+    }
+
+    fn line_count(path: &PathBuf) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn key_fingerprint_is_stable_and_hides_key() {
+        let fingerprint = key_fingerprint("sk-secret");
+        assert_eq!(fingerprint, key_fingerprint("sk-secret"));
+        assert_ne!(fingerprint, key_fingerprint("sk-other"));
+        assert_eq!(fingerprint.len(), 16);
+        assert!(!fingerprint.contains("secret"));
+    }
+
+    #[test]
+    fn load_returns_latest_snapshot_and_skips_unchanged_saves() {
+        let path = temp_path("latest");
+        let store = JsonLinesStore::new(&path);
+        assert!(store.load().unwrap().is_none());
+
+        store.save(&snapshot(1)).unwrap();
+        store.save(&snapshot(2)).unwrap();
+        store.save(&snapshot(2)).unwrap();
+        assert_eq!(line_count(&path), 2);
+
+        let loaded = JsonLinesStore::new(&path).load().unwrap().unwrap();
+        assert_eq!(loaded.provider_usage["openai"], 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_skips_truncated_last_line() {
+        let path = temp_path("truncated");
+        let store = JsonLinesStore::new(&path);
+        store.save(&snapshot(3)).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"provider_usage\": {\"openai\"")
+            .unwrap();
+
+        let loaded = JsonLinesStore::new(&path).load().unwrap().unwrap();
+        assert_eq!(loaded.provider_usage["openai"], 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_compacts_file_after_max_lines() {
+        let path = temp_path("compact");
+        let store = JsonLinesStore::new(&path);
+        for requests in 0..=MAX_LINES as u64 {
+            store.save(&snapshot(requests)).unwrap();
+        }
+        assert_eq!(line_count(&path), 1);
+
+        let loaded = JsonLinesStore::new(&path).load().unwrap().unwrap();
+        assert_eq!(loaded.provider_usage["openai"], MAX_LINES as u64);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::config::{ClientConfig, QuotaConfig};
+
+/// 配额周期，按 UTC 计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "Daily",
+            QuotaPeriod::Monthly => "Monthly",
+        }
+    }
+
+    /// 包含 `day` 的周期的第一天
+    fn start(&self, day: NaiveDate) -> NaiveDate {
+        match self {
+            QuotaPeriod::Daily => day,
+            QuotaPeriod::Monthly => day.with_day(1).unwrap_or(day),
+        }
+    }
+
+    /// 周期结束的时间
+    fn resets_at(&self, start: NaiveDate) -> DateTime<Utc> {
+        let next = match self {
+            QuotaPeriod::Daily => start.succ_opt(),
+            QuotaPeriod::Monthly => start.checked_add_months(Months::new(1)),
+        }
+        .unwrap_or(start);
+        next.and_time(chrono::NaiveTime::MIN).and_utc()
+    }
+
+    /// 配额设置中该周期的 token 和费用上限
+    fn limits(&self, quota: &QuotaConfig) -> (Option<u64>, Option<f64>) {
+        match self {
+            QuotaPeriod::Daily => (quota.daily_tokens, quota.daily_cost),
+            QuotaPeriod::Monthly => (quota.monthly_tokens, quota.monthly_cost),
+        }
+    }
+}
+
+/// 一个周期内的用量和通过管理接口追加的额度，周期结束后一起清零
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct PeriodUsage {
+    start: NaiveDate,
+    tokens: u64,
+    cost: f64,
+    extra_tokens: u64,
+    extra_cost: f64,
+}
+
+impl PeriodUsage {
+    fn new(start: NaiveDate) -> Self {
+        Self {
+            start,
+            tokens: 0,
+            cost: 0.0,
+            extra_tokens: 0,
+            extra_cost: 0.0,
+        }
+    }
+
+    /// 进入新的周期时清零
+    fn roll(&mut self, period: QuotaPeriod, today: NaiveDate) {
+        let start = period.start(today);
+        if self.start != start {
+            *self = Self::new(start);
+        }
+    }
+
+    /// 检查是否已用尽配额，返回拒绝原因
+    fn exhausted(&self, period: QuotaPeriod, quota: &QuotaConfig) -> Option<String> {
+        let (token_limit, cost_limit) = period.limits(quota);
+        let resets_at = period.resets_at(self.start).to_rfc3339();
+        if let Some(limit) = token_limit {
+            let limit = limit.saturating_add(self.extra_tokens);
+            if self.tokens >= limit {
+                return Some(format!(
+                    "{} token quota of {} exhausted, resets at {}",
+                    period.as_str(),
+                    limit,
+                    resets_at
+                ));
+            }
+        }
+        if let Some(limit) = cost_limit {
+            if self.cost >= limit + self.extra_cost {
+                return Some(format!(
+                    "{} cost quota of ${:.4} exhausted, resets at {}",
+                    period.as_str(),
+                    limit + self.extra_cost,
+                    resets_at
+                ));
+            }
+        }
+        None
+    }
+
+    fn snapshot(&self, period: QuotaPeriod, quota: &QuotaConfig) -> Value {
+        let (token_limit, cost_limit) = period.limits(quota);
+        let token_limit = token_limit.map(|limit| limit.saturating_add(self.extra_tokens));
+        let cost_limit = cost_limit.map(|limit| limit + self.extra_cost);
+        json!({
+            "period_start": self.start.to_string(),
+            "resets_at": period.resets_at(self.start).to_rfc3339(),
+            "tokens_used": self.tokens,
+            "tokens_limit": token_limit,
+            "tokens_remaining": token_limit.map(|limit| limit.saturating_sub(self.tokens)),
+            "cost_used": self.cost,
+            "cost_limit": cost_limit,
+            "cost_remaining": cost_limit.map(|limit| (limit - self.cost).max(0.0)),
+            "extra_tokens": self.extra_tokens,
+            "extra_cost": self.extra_cost,
+            "exhausted": self.exhausted(period, quota).is_some(),
+        })
+    }
+}
+
+/// 单个客户端当天和当月的用量
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClientUsage {
+    daily: PeriodUsage,
+    monthly: PeriodUsage,
+}
+
+impl ClientUsage {
+    fn new(today: NaiveDate) -> Self {
+        Self {
+            daily: PeriodUsage::new(QuotaPeriod::Daily.start(today)),
+            monthly: PeriodUsage::new(QuotaPeriod::Monthly.start(today)),
+        }
+    }
+
+    fn roll(&mut self, today: NaiveDate) {
+        self.daily.roll(QuotaPeriod::Daily, today);
+        self.monthly.roll(QuotaPeriod::Monthly, today);
+    }
+
+    fn period_mut(&mut self, period: QuotaPeriod) -> &mut PeriodUsage {
+        match period {
+            QuotaPeriod::Daily => &mut self.daily,
+            QuotaPeriod::Monthly => &mut self.monthly,
+        }
+    }
+}
+
+/// 按客户端统计当天和当月的 token 用量和费用，并检查配额
+#[derive(Default)]
+pub struct QuotaManager {
+    clients: DashMap<String, ClientUsage>,
+}
+
+impl QuotaManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 检查客户端的配额，已用尽时返回拒绝原因
+    pub fn check(&self, client: &str, quota: &QuotaConfig) -> Result<(), String> {
+        let usage = self.current(client);
+        [
+            usage.daily.exhausted(QuotaPeriod::Daily, quota),
+            usage.monthly.exhausted(QuotaPeriod::Monthly, quota),
+        ]
+        .into_iter()
+        .flatten()
+        .next()
+        .map_or(Ok(()), |reason| {
+            Err(format!("Client '{}': {}", client, reason))
+        })
+    }
+
+    /// 记录一次请求的 token 用量和费用
+    pub fn record(&self, client: &str, tokens: u64, cost: f64) {
+        let mut entry = self.entry(client);
+        let usage = &mut *entry;
+        for period in [&mut usage.daily, &mut usage.monthly] {
+            period.tokens = period.tokens.saturating_add(tokens);
+            period.cost += cost;
+        }
+    }
+
+    /// 为当前周期追加额度，周期结束后失效
+    pub fn top_up(&self, client: &str, period: QuotaPeriod, tokens: u64, cost: f64) {
+        let mut usage = self.entry(client);
+        let usage = usage.period_mut(period);
+        usage.extra_tokens = usage.extra_tokens.saturating_add(tokens);
+        usage.extra_cost += cost;
+    }
+
+    /// 清零客户端当前周期的用量和追加额度，未指定周期时清零全部
+    pub fn reset(&self, client: &str, period: Option<QuotaPeriod>) {
+        let today = Utc::now().date_naive();
+        let mut usage = self.entry(client);
+        match period {
+            Some(period) => *usage.period_mut(period) = PeriodUsage::new(period.start(today)),
+            None => *usage = ClientUsage::new(today),
+        }
+    }
+
+    /// 客户端当天和当月的配额状态
+    pub fn snapshot(&self, client: &str, quota: &QuotaConfig) -> Value {
+        let usage = self.current(client);
+        json!({
+            "client": client,
+            "daily": usage.daily.snapshot(QuotaPeriod::Daily, quota),
+            "monthly": usage.monthly.snapshot(QuotaPeriod::Monthly, quota),
+        })
+    }
+
+    /// 所有配置了配额的客户端的配额状态
+    pub fn snapshot_all(&self, clients: &[ClientConfig]) -> Vec<Value> {
+        clients
+            .iter()
+            .filter_map(|client| {
+                let quota = client.quota.as_ref()?;
+                Some(self.snapshot(&client.name, quota))
+            })
+            .collect()
+    }
+
+    /// 导出所有客户端的用量，用于持久化
+    pub fn export(&self) -> BTreeMap<String, ClientUsage> {
+        self.clients
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// 恢复导出的用量，已结束周期的用量在下次访问时清零
+    pub fn restore(&self, clients: BTreeMap<String, ClientUsage>) {
+        self.clients.clear();
+        for (name, usage) in clients {
+            self.clients.insert(name, usage);
+        }
+    }
+
+    /// 客户端当前周期的用量，不存在时返回空用量
+    fn current(&self, client: &str) -> ClientUsage {
+        let today = Utc::now().date_naive();
+        let mut usage = self
+            .clients
+            .get(client)
+            .map(|usage| *usage)
+            .unwrap_or_else(|| ClientUsage::new(today));
+        usage.roll(today);
+        usage
+    }
+
+    fn entry(&self, client: &str) -> dashmap::mapref::one::RefMut<'_, String, ClientUsage> {
+        let today = Utc::now().date_naive();
+        let mut usage = self
+            .clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientUsage::new(today));
+        usage.roll(today);
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn used(start: NaiveDate, tokens: u64, cost: f64) -> PeriodUsage {
+        PeriodUsage {
+            tokens,
+            cost,
+            ..PeriodUsage::new(start)
+        }
+    }
+
+    #[test]
+    fn daily_usage_resets_on_next_day() {
+        let mut usage = used(day(2024, 5, 1), 100, 1.0);
+        usage.extra_tokens = 50;
+
+        usage.roll(QuotaPeriod::Daily, day(2024, 5, 1));
+        assert_eq!(usage.tokens, 100);
+
+        usage.roll(QuotaPeriod::Daily, day(2024, 5, 2));
+        assert_eq!(usage.start, day(2024, 5, 2));
+        assert_eq!(usage.tokens, 0);
+        assert_eq!(usage.cost, 0.0);
+        assert_eq!(usage.extra_tokens, 0);
+    }
+
+    #[test]
+    fn monthly_usage_resets_on_next_month() {
+        let mut usage = used(QuotaPeriod::Monthly.start(day(2024, 1, 15)), 100, 1.0);
+        assert_eq!(usage.start, day(2024, 1, 1));
+
+        usage.roll(QuotaPeriod::Monthly, day(2024, 1, 31));
+        assert_eq!(usage.tokens, 100);
+
+        usage.roll(QuotaPeriod::Monthly, day(2024, 2, 1));
+        assert_eq!(usage.start, day(2024, 2, 1));
+        assert_eq!(usage.tokens, 0);
+    }
+
+    #[test]
+    fn resets_at_is_start_of_next_period() {
+        assert_eq!(
+            QuotaPeriod::Daily.resets_at(day(2024, 12, 31)).to_rfc3339(),
+            "2025-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            QuotaPeriod::Monthly.resets_at(day(2024, 1, 1)).to_rfc3339(),
+            "2024-02-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn exhausted_when_token_or_cost_limit_reached() {
+        let quota = QuotaConfig {
+            daily_tokens: Some(100),
+            daily_cost: Some(2.0),
+            ..Default::default()
+        };
+        let start = day(2024, 5, 1);
+
+        assert!(used(start, 99, 0.0)
+            .exhausted(QuotaPeriod::Daily, &quota)
+            .is_none());
+        assert!(used(start, 100, 0.0)
+            .exhausted(QuotaPeriod::Daily, &quota)
+            .is_some_and(|reason| reason.contains("token quota")));
+        assert!(used(start, 0, 2.5)
+            .exhausted(QuotaPeriod::Daily, &quota)
+            .is_some_and(|reason| reason.contains("cost quota")));
+        // 日配额不影响月周期
+        assert!(used(start, 1000, 10.0)
+            .exhausted(QuotaPeriod::Monthly, &quota)
+            .is_none());
+    }
+
+    #[test]
+    fn top_up_extends_limit() {
+        let quota = QuotaConfig {
+            daily_tokens: Some(100),
+            ..Default::default()
+        };
+        let mut usage = used(day(2024, 5, 1), 120, 0.0);
+        assert!(usage.exhausted(QuotaPeriod::Daily, &quota).is_some());
+
+        usage.extra_tokens = 50;
+        assert!(usage.exhausted(QuotaPeriod::Daily, &quota).is_none());
+    }
+
+    #[test]
+    fn manager_records_and_checks_usage() {
+        let manager = QuotaManager::new();
+        let quota = QuotaConfig {
+            monthly_tokens: Some(10),
+            ..Default::default()
+        };
+
+        manager.record("team-a", 6, 0.0);
+        assert!(manager.check("team-a", &quota).is_ok());
+        manager.record("team-a", 6, 0.0);
+        assert!(manager.check("team-a", &quota).is_err());
+        assert!(manager.check("team-b", &quota).is_ok());
+
+        manager.top_up("team-a", QuotaPeriod::Monthly, 5, 0.0);
+        assert!(manager.check("team-a", &quota).is_ok());
+
+        manager.reset("team-a", None);
+        assert_eq!(
+            manager.snapshot("team-a", &quota)["monthly"]["tokens_used"],
+            0
+        );
+    }
+
+    #[test]
+    fn large_top_up_saturates_instead_of_overflowing() {
+        let manager = QuotaManager::new();
+        let quota = QuotaConfig {
+            daily_tokens: Some(10),
+            ..Default::default()
+        };
+
+        manager.record("team-a", 20, 0.0);
+        manager.top_up("team-a", QuotaPeriod::Daily, u64::MAX, 0.0);
+        manager.top_up("team-a", QuotaPeriod::Daily, u64::MAX, 0.0);
+        assert!(manager.check("team-a", &quota).is_ok());
+        assert_eq!(
+            manager.snapshot("team-a", &quota)["daily"]["tokens_limit"],
+            u64::MAX
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use axum::http::{HeaderMap, HeaderValue};
+use dashmap::DashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+/// 令牌桶，容量为每分钟的额度并按该速率匀速补充
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// 当前剩余额度，TPM 桶在请求结束后按实际用量扣除，可能为负数
+    level: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        Self {
+            level: capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.level = (self.level + elapsed * capacity / 60.0).min(capacity);
+        self.updated_at = now;
+    }
+
+    /// 额度恢复到 `target` 需要等待的时间
+    fn wait_until(&self, capacity: f64, target: f64) -> Duration {
+        if self.level >= target {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((target - self.level) * 60.0 / capacity)
+    }
+}
+
+/// 单个限流对象的状态
+struct ScopeState {
+    requests: Bucket,
+    tokens: Bucket,
+    in_flight: u32,
+}
+
+/// 一个限流对象，如全局、某个客户端或某个模型别名
+#[derive(Debug, Clone)]
+pub struct RateLimitScope {
+    /// 限流对象标识，如 `global`、`client:team-a`、`model:gpt-4`
+    pub key: String,
+    pub limit: RateLimitConfig,
+}
+
+/// 返回给客户端的限流状态，取所有限流对象中剩余额度最少的一个
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitStatus {
+    requests: Option<(u32, u64, Duration)>,
+    tokens: Option<(u64, u64, Duration)>,
+}
+
+impl RateLimitStatus {
+    fn merge_requests(&mut self, limit: u32, remaining: u64, reset: Duration) {
+        if self
+            .requests
+            .is_none_or(|(_, current, _)| remaining < current)
+        {
+            self.requests = Some((limit, remaining, reset));
+        }
+    }
+
+    fn merge_tokens(&mut self, limit: u64, remaining: u64, reset: Duration) {
+        if self
+            .tokens
+            .is_none_or(|(_, current, _)| remaining < current)
+        {
+            self.tokens = Some((limit, remaining, reset));
+        }
+    }
+
+    /// OpenAI 风格的 `x-ratelimit-*` 响应头
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some((limit, remaining, reset)) = self.requests {
+            headers.insert("x-ratelimit-limit-requests", HeaderValue::from(limit));
+            headers.insert(
+                "x-ratelimit-remaining-requests",
+                HeaderValue::from(remaining),
+            );
+            if let Ok(value) = HeaderValue::from_str(&format_reset(reset)) {
+                headers.insert("x-ratelimit-reset-requests", value);
+            }
+        }
+        if let Some((limit, remaining, reset)) = self.tokens {
+            headers.insert("x-ratelimit-limit-tokens", HeaderValue::from(limit));
+            headers.insert("x-ratelimit-remaining-tokens", HeaderValue::from(remaining));
+            if let Ok(value) = HeaderValue::from_str(&format_reset(reset)) {
+                headers.insert("x-ratelimit-reset-tokens", value);
+            }
+        }
+        headers
+    }
+}
+
+/// 按 OpenAI 的格式输出额度恢复时间，如 `20ms`、`6s`、`1m30s`
+fn format_reset(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        return format!("{}ms", duration.as_millis());
+    }
+    let secs = duration.as_secs_f64().ceil() as u64;
+    match secs / 60 {
+        0 => format!("{}s", secs),
+        minutes => format!("{}m{}s", minutes, secs % 60),
+    }
+}
+
+/// 请求被限流
+#[derive(Debug)]
+pub struct RateLimitRejection {
+    pub message: String,
+    pub retry_after: Duration,
+    pub status: RateLimitStatus,
+}
+
+/// 通过限流检查的请求许可，释放时减少并发计数
+pub struct RateLimitPermit {
+    limiter: Arc<RateLimiter>,
+    scopes: Vec<RateLimitScope>,
+    pub status: RateLimitStatus,
+}
+
+impl RateLimitPermit {
+    /// 请求结束后按实际 token 用量扣除 TPM 额度
+    pub fn record_tokens(&self, tokens: u64) {
+        let now = Instant::now();
+        for scope in &self.scopes {
+            let Some(per_minute) = scope.limit.tokens_per_minute else {
+                continue;
+            };
+            if let Some(mut state) = self.limiter.scopes.get_mut(&scope.key) {
+                state.tokens.refill(per_minute as f64, now);
+                state.tokens.level -= tokens as f64;
+            }
+        }
+    }
+}
+
+impl Drop for RateLimitPermit {
+    fn drop(&mut self) {
+        for scope in &self.scopes {
+            if scope.limit.max_concurrent.is_none() {
+                continue;
+            }
+            if let Some(mut state) = self.limiter.scopes.get_mut(&scope.key) {
+                state.in_flight = state.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// 基于令牌桶的限流器，支持每分钟请求数、每分钟 token 数和最大并发请求数
+#[derive(Default)]
+pub struct RateLimiter {
+    scopes: DashMap<String, ScopeState>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 依次检查并扣除每个限流对象的额度，某个对象拒绝时退还之前已扣除的额度
+    ///
+    /// 每个对象的检查和扣除在同一个锁内完成，并发请求不会同时通过最后一个额度
+    pub fn acquire(
+        self: &Arc<Self>,
+        scopes: Vec<RateLimitScope>,
+    ) -> Result<RateLimitPermit, RateLimitRejection> {
+        let now = Instant::now();
+        let mut status = RateLimitStatus::default();
+
+        for (index, scope) in scopes.iter().enumerate() {
+            if let Err(rejection) = self.acquire_scope(scope, now, &mut status) {
+                for acquired in &scopes[..index] {
+                    self.refund(acquired);
+                }
+                return Err(rejection);
+            }
+        }
+
+        Ok(RateLimitPermit {
+            limiter: self.clone(),
+            scopes,
+            status,
+        })
+    }
+
+    fn acquire_scope(
+        &self,
+        scope: &RateLimitScope,
+        now: Instant,
+        status: &mut RateLimitStatus,
+    ) -> Result<(), RateLimitRejection> {
+        let limit = scope.limit;
+        let mut state = self.state(&scope.key, limit, now);
+
+        if let Some(max) = limit.max_concurrent {
+            if state.in_flight >= max {
+                return Err(RateLimitRejection {
+                    message: format!(
+                        "Too many concurrent requests for {} (limit {})",
+                        scope.key, max
+                    ),
+                    retry_after: Duration::from_secs(1),
+                    status: *status,
+                });
+            }
+        }
+
+        if let Some(per_minute) = limit.requests_per_minute {
+            let capacity = per_minute as f64;
+            state.requests.refill(capacity, now);
+            if state.requests.level < 1.0 {
+                let reset = state.requests.wait_until(capacity, 1.0);
+                status.merge_requests(per_minute, 0, reset);
+                return Err(RateLimitRejection {
+                    message: format!(
+                        "Rate limit of {} requests per minute exceeded for {}",
+                        per_minute, scope.key
+                    ),
+                    retry_after: reset,
+                    status: *status,
+                });
+            }
+        }
+
+        if let Some(per_minute) = limit.tokens_per_minute {
+            let capacity = per_minute as f64;
+            state.tokens.refill(capacity, now);
+            if state.tokens.level < 1.0 {
+                let reset = state.tokens.wait_until(capacity, 1.0);
+                status.merge_tokens(per_minute, 0, reset);
+                return Err(RateLimitRejection {
+                    message: format!(
+                        "Rate limit of {} tokens per minute exceeded for {}",
+                        per_minute, scope.key
+                    ),
+                    retry_after: reset,
+                    status: *status,
+                });
+            }
+        }
+
+        if limit.max_concurrent.is_some() {
+            state.in_flight += 1;
+        }
+        if let Some(per_minute) = limit.requests_per_minute {
+            let capacity = per_minute as f64;
+            state.requests.level -= 1.0;
+            let remaining = state.requests.level.max(0.0) as u64;
+            status.merge_requests(
+                per_minute,
+                remaining,
+                state.requests.wait_until(capacity, capacity),
+            );
+        }
+        if let Some(per_minute) = limit.tokens_per_minute {
+            let capacity = per_minute as f64;
+            let remaining = state.tokens.level.max(0.0) as u64;
+            status.merge_tokens(
+                per_minute,
+                remaining,
+                state.tokens.wait_until(capacity, capacity),
+            );
+        }
+        Ok(())
+    }
+
+    /// 退还已扣除的请求额度和并发计数
+    fn refund(&self, scope: &RateLimitScope) {
+        let Some(mut state) = self.scopes.get_mut(&scope.key) else {
+            return;
+        };
+        if scope.limit.max_concurrent.is_some() {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+        if let Some(per_minute) = scope.limit.requests_per_minute {
+            state.requests.level = (state.requests.level + 1.0).min(per_minute as f64);
+        }
+    }
+
+    fn state(
+        &self,
+        key: &str,
+        limit: RateLimitConfig,
+        now: Instant,
+    ) -> dashmap::mapref::one::RefMut<'_, String, ScopeState> {
+        self.scopes
+            .entry(key.to_string())
+            .or_insert_with(|| ScopeState {
+                requests: Bucket::full(limit.requests_per_minute.unwrap_or(0) as f64, now),
+                tokens: Bucket::full(limit.tokens_per_minute.unwrap_or(0) as f64, now),
+                in_flight: 0,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(key: &str, limit: RateLimitConfig) -> RateLimitScope {
+        RateLimitScope {
+            key: key.to_string(),
+            limit,
+        }
+    }
+
+    #[test]
+    fn bucket_refills_at_configured_rate() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(60.0, start);
+        bucket.level = 0.0;
+
+        bucket.refill(60.0, start + Duration::from_secs(10));
+        assert!((bucket.level - 10.0).abs() < 1e-9);
+        assert_eq!(bucket.wait_until(60.0, 11.0), Duration::from_secs(1));
+
+        // 补充不超过容量
+        bucket.refill(60.0, start + Duration::from_secs(600));
+        assert_eq!(bucket.level, 60.0);
+        assert_eq!(bucket.wait_until(60.0, 1.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn requests_per_minute_rejects_when_exhausted() {
+        let limiter = Arc::new(RateLimiter::new());
+        let limit = RateLimitConfig {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        };
+
+        let first = limiter.acquire(vec![scope("global", limit)]).unwrap();
+        assert_eq!(
+            first.status.requests.map(|(_, remaining, _)| remaining),
+            Some(1)
+        );
+        let _second = limiter.acquire(vec![scope("global", limit)]).unwrap();
+        let rejection = limiter.acquire(vec![scope("global", limit)]).err().unwrap();
+        assert!(rejection.message.contains("2 requests per minute"));
+        assert!(rejection.retry_after > Duration::ZERO);
+    }
+
+    #[test]
+    fn concurrency_is_released_with_permit() {
+        let limiter = Arc::new(RateLimiter::new());
+        let limit = RateLimitConfig {
+            max_concurrent: Some(1),
+            ..Default::default()
+        };
+
+        let permit = limiter.acquire(vec![scope("client:a", limit)]).unwrap();
+        assert!(limiter.acquire(vec![scope("client:a", limit)]).is_err());
+        drop(permit);
+        assert!(limiter.acquire(vec![scope("client:a", limit)]).is_ok());
+    }
+
+    #[test]
+    fn rejected_request_refunds_earlier_scopes() {
+        let limiter = Arc::new(RateLimiter::new());
+        let global = RateLimitConfig {
+            requests_per_minute: Some(10),
+            max_concurrent: Some(1),
+            ..Default::default()
+        };
+        let model = RateLimitConfig {
+            max_concurrent: Some(1),
+            ..Default::default()
+        };
+
+        let _busy = limiter.acquire(vec![scope("model:gpt-4", model)]).unwrap();
+        assert!(limiter
+            .acquire(vec![scope("global", global), scope("model:gpt-4", model)])
+            .is_err());
+
+        // 被模型限流拒绝的请求不占用全局的并发数和请求额度
+        let permit = limiter.acquire(vec![scope("global", global)]).unwrap();
+        assert_eq!(
+            permit.status.requests.map(|(_, remaining, _)| remaining),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn tokens_per_minute_charged_after_request() {
+        let limiter = Arc::new(RateLimiter::new());
+        let limit = RateLimitConfig {
+            tokens_per_minute: Some(100),
+            ..Default::default()
+        };
+
+        let permit = limiter.acquire(vec![scope("global", limit)]).unwrap();
+        permit.record_tokens(150);
+        drop(permit);
+
+        let rejection = limiter.acquire(vec![scope("global", limit)]).err().unwrap();
+        assert!(rejection.message.contains("100 tokens per minute"));
+    }
+}
@@ -0,0 +1,114 @@
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::services::adapters::ByteStream;
+
+/// 超时类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// 整个请求（包括响应体）超过总超时
+    Total,
+    /// 等待响应头超过首字节超时
+    FirstByte,
+    /// 流式响应两个数据块之间的间隔超过空闲超时
+    Idle,
+}
+
+/// 单个提供者的超时次数
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TimeoutCounts {
+    pub total: u64,
+    pub first_byte: u64,
+    pub idle: u64,
+}
+
+/// 按提供者统计的超时次数
+#[derive(Default)]
+pub struct TimeoutStats {
+    counts: DashMap<String, TimeoutCounts>,
+}
+
+impl TimeoutStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, provider: &str, kind: TimeoutKind) {
+        let mut counts = self.counts.entry(provider.to_string()).or_default();
+        match kind {
+            TimeoutKind::Total => counts.total += 1,
+            TimeoutKind::FirstByte => counts.first_byte += 1,
+            TimeoutKind::Idle => counts.idle += 1,
+        }
+    }
+
+    /// 所有提供者的超时次数
+    pub fn snapshot(&self) -> Vec<(String, TimeoutCounts)> {
+        self.counts
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        self.counts.clear();
+    }
+}
+
+/// 包装上游响应体，统计总超时，并在两个数据块之间的间隔超过 `idle` 时中断响应
+pub fn guard_body<S>(
+    upstream: S,
+    provider: String,
+    idle: Option<Duration>,
+    stats: Arc<TimeoutStats>,
+) -> ByteStream
+where
+    S: futures::Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+{
+    let upstream = upstream.boxed();
+    stream::unfold(Some(upstream), move |state| {
+        let provider = provider.clone();
+        let stats = stats.clone();
+        async move {
+            let mut upstream = state?;
+            let next = match idle {
+                Some(idle) => match tokio::time::timeout(idle, upstream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        warn!(
+                            "Upstream stream from provider '{}' idle for more than {}s, aborting",
+                            provider,
+                            idle.as_secs()
+                        );
+                        stats.record(&provider, TimeoutKind::Idle);
+                        let error =
+                            io::Error::new(io::ErrorKind::TimedOut, "upstream idle timeout");
+                        return Some((Err(error), None));
+                    }
+                },
+                None => upstream.next().await,
+            };
+
+            match next? {
+                Ok(chunk) => Some((Ok(chunk), Some(upstream))),
+                Err(e) => {
+                    let kind = if e.is_timeout() {
+                        warn!("Upstream stream from provider '{}' timed out", provider);
+                        stats.record(&provider, TimeoutKind::Total);
+                        io::ErrorKind::TimedOut
+                    } else {
+                        io::ErrorKind::Other
+                    };
+                    Some((Err(io::Error::new(kind, e.without_url())), None))
+                }
+            }
+        }
+    })
+    .boxed()
+}
@@ -0,0 +1,646 @@
+use bytes::Bytes;
+use chrono::{NaiveDate, Utc};
+use dashmap::DashMap;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::AddAssign;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tracing::debug;
+
+use crate::config::ModelPricing;
+use crate::services::adapters::{anthropic, ByteStream};
+use crate::services::ai::EndpointType;
+
+/// 一次请求的 token 用量
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// 命中提示缓存的输入 token 数，包含在 `prompt_tokens` 中
+    pub cached_tokens: u64,
+}
+
+impl TokenUsage {
+    /// 解析 OpenAI 格式的 usage 对象，其他格式的响应在转换后也使用该格式
+    pub fn from_openai(usage: &Value) -> Option<Self> {
+        if !usage.is_object() {
+            return None;
+        }
+        let prompt_tokens = usage["prompt_tokens"].as_u64().unwrap_or(0);
+        let completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+        Some(Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: usage["total_tokens"]
+                .as_u64()
+                .unwrap_or(prompt_tokens + completion_tokens),
+            cached_tokens: usage["prompt_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .unwrap_or(0),
+        })
+    }
+
+    /// 解析 Anthropic 格式的 usage 对象，缓存命中和写入的 token 都计入 `prompt_tokens`
+    pub fn from_anthropic(usage: &Value) -> Option<Self> {
+        if !usage.is_object() {
+            return None;
+        }
+        Self::from_openai(&anthropic::convert_usage(usage))
+    }
+
+    /// 按模型价格计算费用（美元）
+    pub fn cost(&self, pricing: &ModelPricing) -> f64 {
+        let cached = self.cached_tokens.min(self.prompt_tokens);
+        let uncached = self.prompt_tokens - cached;
+        (uncached as f64 * pricing.input
+            + cached as f64 * pricing.cached_input.unwrap_or(pricing.input)
+            + self.completion_tokens as f64 * pricing.output)
+            / 1_000_000.0
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cached_tokens += other.cached_tokens;
+    }
+}
+
+/// 累计用量
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    /// 带有用量信息的请求数
+    pub requests: u64,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
+    /// 估算费用（美元），未配置价格的模型不计入
+    pub cost: f64,
+}
+
+/// 当天（UTC）的累计费用
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DailySpend {
+    day: NaiveDate,
+    cost: f64,
+}
+
+/// 累计用量的完整记录，用于持久化
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageRecords {
+    #[serde(default)]
+    pub by_provider: BTreeMap<String, UsageTotals>,
+    #[serde(default)]
+    pub by_alias: BTreeMap<String, UsageTotals>,
+    #[serde(default)]
+    pub by_key: BTreeMap<String, UsageTotals>,
+    #[serde(default)]
+    pub by_client: BTreeMap<String, UsageTotals>,
+    pub daily_spend: Option<DailySpend>,
+}
+
+/// 一次请求用量的统计维度
+#[derive(Debug, Clone)]
+pub struct UsageLabels {
+    pub provider: String,
+    /// 客户端请求的模型别名
+    pub alias: String,
+    /// 上游密钥指纹，脱敏后的密钥可能重复，无法区分不同的密钥
+    pub key: String,
+    /// 客户端标识
+    pub client: String,
+}
+
+/// 按提供者、模型别名、密钥和客户端累计 token 用量
+pub struct UsageTracker {
+    by_provider: DashMap<String, UsageTotals>,
+    by_alias: DashMap<String, UsageTotals>,
+    by_key: DashMap<String, UsageTotals>,
+    by_client: DashMap<String, UsageTotals>,
+    daily_spend: Mutex<DailySpend>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            by_provider: DashMap::new(),
+            by_alias: DashMap::new(),
+            by_key: DashMap::new(),
+            by_client: DashMap::new(),
+            daily_spend: Mutex::new(DailySpend {
+                day: Utc::now().date_naive(),
+                cost: 0.0,
+            }),
+        }
+    }
+
+    /// 记录一次请求的用量和费用，返回记录前后当天的累计费用
+    pub fn record(&self, labels: &UsageLabels, usage: TokenUsage, cost: f64) -> (f64, f64) {
+        for (totals, name) in [
+            (&self.by_provider, &labels.provider),
+            (&self.by_alias, &labels.alias),
+            (&self.by_key, &labels.key),
+            (&self.by_client, &labels.client),
+        ] {
+            let mut entry = totals.entry(name.clone()).or_default();
+            entry.requests += 1;
+            entry.tokens += usage;
+            entry.cost += cost;
+        }
+
+        let mut daily = self.daily_spend.lock().unwrap_or_else(|e| e.into_inner());
+        let today = Utc::now().date_naive();
+        if daily.day != today {
+            *daily = DailySpend {
+                day: today,
+                cost: 0.0,
+            };
+        }
+        let before = daily.cost;
+        daily.cost += cost;
+        (before, daily.cost)
+    }
+
+    pub fn snapshot(&self) -> Value {
+        let collect = |totals: &DashMap<String, UsageTotals>| {
+            totals
+                .iter()
+                .map(|entry| (entry.key().clone(), json!(*entry.value())))
+                .collect::<serde_json::Map<_, _>>()
+        };
+        let daily = *self.daily_spend.lock().unwrap_or_else(|e| e.into_inner());
+        json!({
+            "total_cost": self.by_provider.iter().map(|entry| entry.cost).sum::<f64>(),
+            "daily_spend": {
+                "date": daily.day.to_string(),
+                "cost": daily.cost,
+            },
+            "by_provider": collect(&self.by_provider),
+            "by_alias": collect(&self.by_alias),
+            "by_key": collect(&self.by_key),
+            "by_client": collect(&self.by_client),
+        })
+    }
+
+    /// 导出全部累计用量
+    pub fn export(&self) -> UsageRecords {
+        let collect = |totals: &DashMap<String, UsageTotals>| {
+            totals
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect()
+        };
+        UsageRecords {
+            by_provider: collect(&self.by_provider),
+            by_alias: collect(&self.by_alias),
+            by_key: collect(&self.by_key),
+            by_client: collect(&self.by_client),
+            daily_spend: Some(*self.daily_spend.lock().unwrap_or_else(|e| e.into_inner())),
+        }
+    }
+
+    /// 恢复导出的累计用量，不是当天的费用记录会被忽略
+    pub fn restore(&self, records: UsageRecords) {
+        for (totals, restored) in [
+            (&self.by_provider, records.by_provider),
+            (&self.by_alias, records.by_alias),
+            (&self.by_key, records.by_key),
+            (&self.by_client, records.by_client),
+        ] {
+            totals.clear();
+            for (name, restored) in restored {
+                totals.insert(name, restored);
+            }
+        }
+        if let Some(daily) = records.daily_spend {
+            if daily.day == Utc::now().date_naive() {
+                *self.daily_spend.lock().unwrap_or_else(|e| e.into_inner()) = daily;
+            }
+        }
+    }
+}
+
+/// 响应结束时的回调，参数为从响应中解析到的用量
+pub type UsageCallback = Box<dyn FnOnce(Option<TokenUsage>) + Send>;
+
+/// 在响应体经过时解析用量，响应体传输结束或客户端断开时调用 `on_complete`
+///
+/// `EndpointType::Messages` 的响应为 Anthropic 格式，其他为 OpenAI 格式；`hide_usage` 为 true 时，
+/// 代理自行注入 `stream_options.include_usage` 产生的用量块不会返回给客户端
+pub fn tap_usage(
+    body: ByteStream,
+    endpoint_type: EndpointType,
+    stream: bool,
+    hide_usage: bool,
+    on_complete: UsageCallback,
+) -> ByteStream {
+    let anthropic = matches!(endpoint_type, EndpointType::Messages);
+    if stream {
+        let tap = Arc::new(Mutex::new(SseUsageTap {
+            pending: Vec::new(),
+            hide_usage,
+            anthropic_usage: anthropic.then(Map::new),
+            usage: None,
+            on_complete: Some(on_complete),
+        }));
+        let finisher = tap.clone();
+        let chunks = body.map(move |chunk| {
+            chunk.map(|bytes| tap.lock().unwrap_or_else(|e| e.into_inner()).push(bytes))
+        });
+        let tail =
+            stream::once(
+                async move { Ok(finisher.lock().unwrap_or_else(|e| e.into_inner()).finish()) },
+            );
+        return DrainOnDisconnect::wrap(
+            chunks
+                .chain(tail)
+                .filter(|chunk| {
+                    let keep = !matches!(chunk, Ok(bytes) if bytes.is_empty());
+                    async move { keep }
+                })
+                .boxed(),
+        );
+    }
+
+    let mut tap = JsonUsageTap {
+        buffer: Vec::new(),
+        anthropic,
+        on_complete: Some(on_complete),
+    };
+    DrainOnDisconnect::wrap(
+        body.map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                tap.buffer.extend_from_slice(bytes);
+            }
+            chunk
+        })
+        .boxed(),
+    )
+}
+
+/// 客户端提前断开时在后台读完剩余的响应体
+///
+/// 用量通常在响应的最后返回，不读完就无法计入限流、配额和费用，读取仍受上游超时限制
+struct DrainOnDisconnect {
+    /// 响应体传输结束后为空
+    body: Option<ByteStream>,
+}
+
+impl DrainOnDisconnect {
+    fn wrap(body: ByteStream) -> ByteStream {
+        Self { body: Some(body) }.boxed()
+    }
+}
+
+impl Stream for DrainOnDisconnect {
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(body) = self.body.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let next = futures::ready!(body.poll_next_unpin(cx));
+        if next.is_none() {
+            self.body = None;
+        }
+        Poll::Ready(next)
+    }
+}
+
+impl Drop for DrainOnDisconnect {
+    fn drop(&mut self) {
+        let Some(mut body) = self.body.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        debug!("Client disconnected early, draining upstream response for token usage");
+        runtime.spawn(async move { while body.next().await.is_some() {} });
+    }
+}
+
+/// 从流式响应的副本中解析带有 usage 的数据块，上游的字节原样返回给客户端
+///
+/// 需要去掉用量块时按事件边界返回，其他事件的 `event:`、`id:` 字段和注释行保持不变
+struct SseUsageTap {
+    /// 还没有遇到事件结尾空行的字节
+    pending: Vec<u8>,
+    hide_usage: bool,
+    /// Anthropic 格式的响应累计的 usage 字段，OpenAI 格式的响应为 None
+    anthropic_usage: Option<Map<String, Value>>,
+    usage: Option<TokenUsage>,
+    on_complete: Option<UsageCallback>,
+}
+
+impl SseUsageTap {
+    /// 处理上游的一个数据块，返回需要发给客户端的字节
+    fn push(&mut self, bytes: Bytes) -> Bytes {
+        self.pending.extend_from_slice(&bytes);
+        let mut output = Vec::new();
+        while let Some(end) = event_end(&self.pending) {
+            let event: Vec<u8> = self.pending.drain(..end).collect();
+            if self.inspect(&event) && self.hide_usage {
+                output.extend_from_slice(&event);
+            }
+        }
+        if self.hide_usage {
+            Bytes::from(output)
+        } else {
+            bytes
+        }
+    }
+
+    /// 上游流结束，处理最后一个没有以空行结尾的事件
+    fn finish(&mut self) -> Bytes {
+        let event = std::mem::take(&mut self.pending);
+        if self.inspect(&event) && self.hide_usage {
+            Bytes::from(event)
+        } else {
+            Bytes::new()
+        }
+    }
+
+    /// 解析事件中的用量，返回该事件是否需要发给客户端
+    fn inspect(&mut self, event: &[u8]) -> bool {
+        let Ok(chunk) = serde_json::from_str::<Value>(&event_data(event)) else {
+            return true;
+        };
+        if let Some(merged) = &mut self.anthropic_usage {
+            // Anthropic 的输入用量在 message_start 中，输出用量在 message_delta 中
+            let usage = match chunk["type"].as_str() {
+                Some("message_start") => &chunk["message"]["usage"],
+                Some("message_delta") => &chunk["usage"],
+                _ => return true,
+            };
+            for (key, value) in usage.as_object().into_iter().flatten() {
+                if !value.is_null() {
+                    merged.insert(key.clone(), value.clone());
+                }
+            }
+            self.usage = TokenUsage::from_anthropic(&Value::Object(merged.clone()));
+            return true;
+        }
+        let Some(usage) = TokenUsage::from_openai(&chunk["usage"]) else {
+            return true;
+        };
+        self.usage = Some(usage);
+        let usage_only = chunk["choices"].as_array().is_some_and(|c| c.is_empty());
+        !(usage_only && self.hide_usage)
+    }
+}
+
+impl Drop for SseUsageTap {
+    fn drop(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.usage);
+        }
+    }
+}
+
+/// 第一个完整事件的结束位置（包含结尾的空行），行尾可以是 `\n`、`\r\n` 或 `\r`
+fn event_end(buffer: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < buffer.len() {
+        let newline = match buffer[i] {
+            b'\n' => 1,
+            b'\r' => match buffer.get(i + 1) {
+                Some(b'\n') => 2,
+                Some(_) => 1,
+                // 无法判断是否为 \r\n，等待下一个数据块
+                None => return None,
+            },
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        if i == line_start {
+            return Some(i + newline);
+        }
+        i += newline;
+        line_start = i;
+    }
+    None
+}
+
+/// 拼接事件中所有 `data:` 行的内容，多行之间以换行分隔
+fn event_data(event: &[u8]) -> String {
+    String::from_utf8_lossy(event)
+        .split(['\n', '\r'])
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 缓存完整的 JSON 响应，结束时解析其中的 usage
+struct JsonUsageTap {
+    buffer: Vec<u8>,
+    /// 响应是否为 Anthropic 格式
+    anthropic: bool,
+    on_complete: Option<UsageCallback>,
+}
+
+impl Drop for JsonUsageTap {
+    fn drop(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            let usage = serde_json::from_slice::<Value>(&self.buffer)
+                .ok()
+                .and_then(|body| {
+                    if self.anthropic {
+                        TokenUsage::from_anthropic(&body["usage"])
+                    } else {
+                        TokenUsage::from_openai(&body["usage"])
+                    }
+                });
+            on_complete(usage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// 依次发送数据块，返回客户端收到的响应体和回调得到的用量
+    async fn run_tap(
+        chunks: &[&str],
+        endpoint_type: EndpointType,
+        stream: bool,
+        hide_usage: bool,
+    ) -> (String, Option<TokenUsage>) {
+        let chunks: Vec<Result<Bytes, io::Error>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from(chunk.to_string())))
+            .collect();
+        let (sender, receiver) = mpsc::channel();
+        let body = tap_usage(
+            stream::iter(chunks).boxed(),
+            endpoint_type,
+            stream,
+            hide_usage,
+            Box::new(move |usage| sender.send(usage).unwrap()),
+        );
+        let output: Vec<Bytes> = body.map(|chunk| chunk.unwrap()).collect().await;
+        let output = String::from_utf8(output.concat()).unwrap();
+        (output, receiver.recv().unwrap())
+    }
+
+    #[test]
+    fn from_openai_reads_cached_tokens() {
+        let usage = TokenUsage::from_openai(&json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 20,
+            "prompt_tokens_details": {"cached_tokens": 40}
+        }))
+        .unwrap();
+        assert_eq!(
+            usage,
+            TokenUsage {
+                prompt_tokens: 100,
+                completion_tokens: 20,
+                total_tokens: 120,
+                cached_tokens: 40,
+            }
+        );
+        assert_eq!(TokenUsage::from_openai(&Value::Null), None);
+    }
+
+    #[test]
+    fn cost_uses_cached_input_price() {
+        let usage = TokenUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 1_000_000,
+            total_tokens: 2_000_000,
+            cached_tokens: 500_000,
+        };
+        let pricing = ModelPricing {
+            input: 2.0,
+            output: 8.0,
+            cached_input: Some(0.5),
+        };
+        assert!((usage.cost(&pricing) - 9.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn event_end_handles_all_line_endings() {
+        assert_eq!(event_end(b"data: a\n\ndata: b"), Some(9));
+        assert_eq!(event_end(b"data: a\r\n\r\n"), Some(11));
+        assert_eq!(event_end(b"data: a\r\rdata: b"), Some(9));
+        assert_eq!(event_end(b"data: a\n"), None);
+        // 结尾的 \r 可能是 \r\n 的一部分
+        assert_eq!(event_end(b"data: a\r\n\r"), None);
+    }
+
+    #[tokio::test]
+    async fn stream_is_forwarded_unchanged() {
+        let chunks = [
+            ": keep-alive\n\n",
+            "event: chunk\nid: 1\ndata: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n",
+            "\n",
+            "data: {\"choices\":[],\n",
+            "data: \"usage\":{\"prompt_tokens\":3,\"completion_tokens\":2}}\n\n",
+            "data: [DONE]\n\n",
+        ];
+        let (output, usage) = run_tap(&chunks, EndpointType::Completions, true, false).await;
+        assert_eq!(output, chunks.concat());
+        // 多行 data 拼接后解析
+        assert_eq!(usage.map(|u| u.total_tokens), Some(5));
+    }
+
+    #[tokio::test]
+    async fn injected_usage_chunk_is_hidden() {
+        let chunks = [
+            "event: chunk\r\ndata: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\r\n\r\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":2}}\n\n",
+            "data: [DO",
+            "NE]",
+        ];
+        let (output, usage) = run_tap(&chunks, EndpointType::Completions, true, true).await;
+        assert_eq!(
+            output,
+            "event: chunk\r\ndata: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\r\n\r\ndata: [DONE]"
+        );
+        assert_eq!(usage.map(|u| u.prompt_tokens), Some(3));
+    }
+
+    #[tokio::test]
+    async fn anthropic_usage_is_merged_from_events() {
+        let chunks = [
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":10,\"cache_read_input_tokens\":4,\"output_tokens\":1}}}\n\n",
+            "event: ping\ndata: {\"type\":\"ping\"}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":6}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        ];
+        let (output, usage) = run_tap(&chunks, EndpointType::Messages, true, false).await;
+        assert_eq!(output, chunks.concat());
+        assert_eq!(
+            usage,
+            Some(TokenUsage {
+                prompt_tokens: 14,
+                completion_tokens: 6,
+                total_tokens: 20,
+                cached_tokens: 4,
+            })
+        );
+
+        let (_, usage) = run_tap(
+            &["{\"usage\":{\"input_tokens\":3,\"output_tokens\":2}}"],
+            EndpointType::Messages,
+            false,
+            false,
+        )
+        .await;
+        assert_eq!(usage.map(|u| u.total_tokens), Some(5));
+    }
+
+    #[tokio::test]
+    async fn json_usage_is_parsed_at_end() {
+        let chunks = [
+            "{\"choices\":[],",
+            "\"usage\":{\"prompt_tokens\":7,\"completion_tokens\":1}}",
+        ];
+        let (output, usage) = run_tap(&chunks, EndpointType::Completions, false, false).await;
+        assert_eq!(output, chunks.concat());
+        assert_eq!(usage.map(|u| u.total_tokens), Some(8));
+
+        let (_, usage) = run_tap(&["not json"], EndpointType::Completions, false, false).await;
+        assert_eq!(usage, None);
+    }
+
+    #[test]
+    fn tracker_accumulates_by_label() {
+        let tracker = UsageTracker::new();
+        let labels = UsageLabels {
+            provider: "openai".to_string(),
+            alias: "gpt-4".to_string(),
+            key: "fp".to_string(),
+            client: "team-a".to_string(),
+        };
+        let usage = TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+            cached_tokens: 0,
+        };
+        tracker.record(&labels, usage, 0.5);
+        let (_, after) = tracker.record(&labels, usage, 0.25);
+        assert!((after - 0.75).abs() < 1e-9);
+
+        let records = tracker.export();
+        let totals = &records.by_client["team-a"];
+        assert_eq!(totals.requests, 2);
+        assert_eq!(totals.tokens.total_tokens, 30);
+        assert_eq!(records.by_provider["openai"].tokens.prompt_tokens, 20);
+    }
+}
@@ -1,12 +1,46 @@
 use dashmap::DashMap;
 use ipnet::IpNet;
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+use tracing::{info, warn};
 
-use crate::config::Config;
+use crate::config::{Config, ModelPricing, Provider};
 use crate::error::AppResult;
+use crate::services::audit::{AuditLogger, AuditRecord};
+use crate::services::balancer::LoadBalancer;
+use crate::services::capture::CaptureLogger;
+use crate::services::key_health::KeyHealthManager;
+use crate::services::key_selector::KeySelectors;
+use crate::services::metrics::Metrics;
+use crate::services::persistence::{self, UsageSnapshot, UsageStore};
+use crate::services::quota::QuotaManager;
+use crate::services::rate_limit::RateLimiter;
+use crate::services::timeouts::TimeoutStats;
+use crate::services::usage::UsageTracker;
+
+/// 模型别名解析后的一个转发目标
+#[derive(Debug, Clone)]
+pub struct RouteTarget {
+    pub provider: Provider,
+    /// 上游真实模型名称
+    pub model: String,
+    /// 优先级，数值越小越先尝试
+    pub priority: u32,
+    /// 加权随机策略下的权重
+    pub weight: u32,
+    /// 模型价格，未配置时不计算费用
+    pub pricing: Option<ModelPricing>,
+}
+
+impl RouteTarget {
+    /// 目标的唯一标识，格式为 provider:model
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.provider.name, self.model)
+    }
+}
 
 #[derive(Clone)]
 pub struct AppState {
@@ -15,6 +49,20 @@ pub struct AppState {
     pub provider_usage: Arc<RwLock<DashMap<String, u64>>>,
     pub key_usage: Arc<RwLock<DashMap<String, u64>>>,
     pub ip_ban_manager: Arc<IpBanManager>,
+    pub load_balancer: Arc<LoadBalancer>,
+    pub key_health: Arc<KeyHealthManager>,
+    pub key_selectors: Arc<KeySelectors>,
+    pub timeout_stats: Arc<TimeoutStats>,
+    pub usage_tracker: Arc<UsageTracker>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub quota_manager: Arc<QuotaManager>,
+    /// 统计数据存储，未配置持久化时为 `None`
+    pub usage_store: Option<Arc<dyn UsageStore>>,
+    /// 请求审计日志，未配置时为 `None`
+    pub audit_log: Option<Arc<AuditLogger>>,
+    /// 请求和响应内容捕获，未配置时为 `None`
+    pub capture_log: Option<Arc<CaptureLogger>>,
+    pub metrics: Arc<Metrics>,
 }
 
 /// IP封禁管理器
@@ -279,6 +327,11 @@ impl IpBanManager {
         tracing::info!("IP {} authentication successful, failure record reset", ip);
     }
 
+    /// 被封禁的 IPv4 地址和 IPv6 网段总数
+    pub fn banned_count(&self) -> usize {
+        self.banned_ipv4.len() + self.banned_ipv6_networks.len()
+    }
+
     /// 获取IP的失败次数
     pub fn get_failure_count(&self, ip: &str) -> u32 {
         if let Ok(ip_addr) = ip.parse::<IpAddr>() {
@@ -351,13 +404,128 @@ impl AppState {
             .connect_timeout(std::time::Duration::from_secs(10))
             .build()?;
 
-        Ok(Self {
+        let persistence = config.persistence.clone();
+        let audit_log = config
+            .audit_log
+            .as_ref()
+            .map(|audit| Arc::new(AuditLogger::new(audit)));
+        let capture_log = config
+            .capture
+            .as_ref()
+            .map(|capture| Arc::new(CaptureLogger::new(capture)));
+        let state = Self {
             config: Arc::new(RwLock::new(config)),
             http_client,
             provider_usage: Arc::new(RwLock::new(DashMap::new())),
             key_usage: Arc::new(RwLock::new(DashMap::new())),
             ip_ban_manager: Arc::new(IpBanManager::new(5)), // 失败5次封禁
-        })
+            load_balancer: Arc::new(LoadBalancer::new()),
+            key_health: Arc::new(KeyHealthManager::new()),
+            key_selectors: Arc::new(KeySelectors::new()),
+            timeout_stats: Arc::new(TimeoutStats::new()),
+            usage_tracker: Arc::new(UsageTracker::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            quota_manager: Arc::new(QuotaManager::new()),
+            usage_store: persistence.as_ref().map(persistence::open_store),
+            audit_log,
+            capture_log,
+            metrics: Arc::new(Metrics::new()),
+        };
+
+        if let (Some(persistence), Some(store)) = (persistence, state.usage_store.clone()) {
+            // 读取失败时从零开始统计，不影响启动
+            match store.load() {
+                Ok(Some(snapshot)) => {
+                    state.restore_usage(snapshot).await;
+                    info!("Restored usage statistics from '{}'", persistence.path);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "Failed to load usage statistics from '{}': {}",
+                    persistence.path, e
+                ),
+            }
+
+            // 定期写入统计数据
+            let flush_state = state.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(persistence.flush_interval());
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    flush_state.flush_usage().await;
+                }
+            });
+        }
+
+        Ok(state)
+    }
+
+    /// 当前的统计数据快照
+    pub async fn usage_snapshot(&self) -> UsageSnapshot {
+        let provider_usage = self.provider_usage.read().await;
+        let key_usage = self.key_usage.read().await;
+        UsageSnapshot {
+            provider_usage: provider_usage
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect(),
+            key_usage: key_usage
+                .iter()
+                .map(|entry| (persistence::key_fingerprint(entry.key()), *entry.value()))
+                .collect(),
+            token_usage: self.usage_tracker.export(),
+            quotas: self.quota_manager.export(),
+        }
+    }
+
+    /// 恢复统计数据，已从配置中移除的密钥的使用次数会被丢弃
+    async fn restore_usage(&self, snapshot: UsageSnapshot) {
+        let keys: HashMap<String, String> = self
+            .config
+            .read()
+            .await
+            .providers
+            .iter()
+            .flat_map(|provider| provider.keys.iter())
+            .map(|key| (persistence::key_fingerprint(key), key.clone()))
+            .collect();
+
+        let mut provider_usage = self.provider_usage.write().await;
+        provider_usage.clear();
+        provider_usage.extend(snapshot.provider_usage);
+
+        let mut key_usage = self.key_usage.write().await;
+        key_usage.clear();
+        key_usage.extend(
+            snapshot
+                .key_usage
+                .into_iter()
+                .filter_map(|(fingerprint, count)| Some((keys.get(&fingerprint)?.clone(), count))),
+        );
+
+        self.usage_tracker.restore(snapshot.token_usage);
+        self.quota_manager.restore(snapshot.quotas);
+    }
+
+    /// 写入审计记录，未配置审计日志时不做任何事
+    pub fn audit(&self, record: AuditRecord) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.log(record);
+        }
+    }
+
+    /// 将统计数据写入存储，未配置持久化时不做任何事
+    pub async fn flush_usage(&self) {
+        let Some(store) = self.usage_store.clone() else {
+            return;
+        };
+        let snapshot = self.usage_snapshot().await;
+        match tokio::task::spawn_blocking(move || store.save(&snapshot)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to save usage statistics: {}", e),
+            Err(e) => warn!("Usage statistics flush task failed: {}", e),
+        }
     }
 
     pub async fn reload_config(&self) -> AppResult<()> {
@@ -367,48 +535,58 @@ impl AppState {
         Ok(())
     }
 
-    pub async fn get_provider_by_model(&self, model: &str) -> Option<crate::config::Provider> {
+    /// 解析模型别名对应的所有转发目标，按优先级和负载均衡策略排序
+    ///
+    /// `provider:model` 格式只会得到一个目标；普通别名会得到所有配置了该别名的提供者，
+    /// 转发时按顺序依次尝试
+    pub async fn get_route_targets(&self, alias: &str) -> Vec<RouteTarget> {
         let config = self.config.read().await;
 
         // 检查是否是 provider:model 格式
-        if let Some((provider_name, _model_name)) = model.split_once(':') {
-            // 如果是 provider:model 格式，直接查找对应的provider
-            config
+        if let Some((provider_name, model_name)) = alias.split_once(':') {
+            // 如果是 provider:model 格式，直接查找对应的provider，模型名称即冒号后面的部分
+            return config
                 .providers
                 .iter()
                 .find(|provider| provider.name == provider_name)
-                .cloned()
-        } else {
-            // 如果不是 provider:model 格式，使用原来的查找逻辑
-            config
-                .providers
-                .iter()
-                .find(|provider| provider.models.iter().any(|m| m.alias == model))
-                .cloned()
+                .map(|provider| RouteTarget {
+                    provider: provider.clone(),
+                    model: model_name.to_string(),
+                    priority: 0,
+                    weight: 1,
+                    // 使用该提供者下相同上游模型的价格
+                    pricing: provider
+                        .models
+                        .iter()
+                        .find(|m| m.model == model_name)
+                        .and_then(|m| m.pricing),
+                })
+                .into_iter()
+                .collect();
         }
-    }
 
-    pub async fn get_model_mapping(&self, alias: &str) -> Option<String> {
-        let config = self.config.read().await;
-
-        // 检查是否是 provider:model 格式
-        if let Some((provider_name, model_name)) = alias.split_once(':') {
-            // 如果是 provider:model 格式，查找对应的provider和model
-            for provider in &config.providers {
-                if provider.name == provider_name {
-                    // 如果provider找到了，返回model名称（即冒号后面的部分）
-                    return Some(model_name.to_string());
-                }
-            }
-            None
-        } else {
-            // 如果不是 provider:model 格式，使用原来的查找逻辑
-            for provider in &config.providers {
-                if let Some(model) = provider.models.iter().find(|m| m.alias == alias) {
-                    return Some(model.model.clone());
-                }
-            }
-            None
-        }
+        // 如果不是 provider:model 格式，收集所有包含该别名的provider
+        let mut targets: Vec<RouteTarget> = config
+            .providers
+            .iter()
+            .flat_map(|provider| {
+                provider
+                    .models
+                    .iter()
+                    .filter(|m| m.alias == alias)
+                    .map(move |m| RouteTarget {
+                        provider: provider.clone(),
+                        model: m.model.clone(),
+                        priority: m.priority.unwrap_or(0),
+                        weight: m.weight.unwrap_or(1),
+                        pricing: m.pricing,
+                    })
+            })
+            .collect();
+
+        // 稳定排序，相同优先级保持配置文件中的顺序，再按负载均衡策略调整组内顺序
+        targets.sort_by_key(|t| t.priority);
+        self.load_balancer
+            .order(alias, config.balance_strategy(alias), targets)
     }
 }